@@ -28,6 +28,7 @@ fn make_dummy_tracks() -> Vec<AudioTrack> {
         artist: Some(artist.to_string()),
         duration: Some(120.0),
         position: 0,
+        ..Default::default()
     };
     vec![
         mk("samples/track1.mp3", "Sample One", "Artist A"),
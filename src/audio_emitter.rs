@@ -3,18 +3,25 @@ use std::path::Path;
 use std::time::{Duration, Instant};
 use std::thread;
 use anyhow::{Result, anyhow};
-use rodio::{OutputStream, OutputStreamHandle, Sink, Decoder};
+use rodio::{OutputStream, OutputStreamHandle, Sink, Decoder, Source};
+use rodio::source::SeekError;
 use std::io::BufReader;
 use tokio::sync::mpsc::{self, Sender, Receiver};
 
+use crate::audio_queue::AudioTrack;
+
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub enum EmitterCommand {
     Play(String),
+    /// Play a queued track, honoring its start and end offsets
+    PlayTrack(AudioTrack),
     Pause,
     Resume,
     Stop,
     Volume(f32),
+    /// Gain offset in dB of the track that is playing
+    TrackGain(f64),
     Seek(f64),
     GetStatus,
 }
@@ -29,6 +36,8 @@ pub enum EmitterState {
 pub struct AudioEmitter {
     state: EmitterState,
     volume: f32,
+    /// Linear gain of the loaded track, multiplied with `volume`
+    gain: f32,
     current_file: Option<String>,
     position: f64,
     #[allow(dead_code)]
@@ -62,6 +71,7 @@ impl AudioEmitter {
         Ok(Self {
             state: EmitterState::Stopped,
             volume: 1.0,
+            gain: 1.0,
             current_file: None,
             position: 0.0,
             duration: None,
@@ -78,8 +88,27 @@ impl AudioEmitter {
     }
 
     pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.gain = 1.0;
+        self.load_section(path, None, None)
+    }
+
+    /// Load a queued track, playing only the part between its start and end offsets
+    /// with its gain offset applied
+    pub fn load_track(&mut self, track: &AudioTrack) -> Result<()> {
+        self.gain = track.gain_factor();
+        self.load_section(&track.path, track.start, track.end)
+    }
+
+    /// Load the part of a file from `start` to `end`, in seconds. Missing offsets
+    /// mean the start and the end of the file.
+    pub fn load_section<P: AsRef<Path>>(&mut self, path: P, start: Option<f64>, end: Option<f64>) -> Result<()> {
         let path_str = path.as_ref().to_string_lossy().to_string();
 
+        let start = start.unwrap_or(0.0);
+        if start < 0.0 || end.is_some_and(|end| end <= start) {
+            return Err(anyhow!("Invalid section {:.2}s..{:?} of {}", start, end, path_str));
+        }
+
         // Check if file exists
         if !path.as_ref().exists() {
             return Err(anyhow!("File does not exist: {}", path_str));
@@ -96,7 +125,7 @@ impl AudioEmitter {
             .map_err(|e| anyhow!("Failed to open file {}: {}", path_str, e))?;
 
         match Decoder::new(BufReader::new(file)) {
-            Ok(source) => {
+            Ok(mut source) => {
                 if let Some(stream_handle) = &self.stream_handle {
                     // Create new sink
                     let sink = Sink::try_new(stream_handle)
@@ -104,8 +133,23 @@ impl AudioEmitter {
 
                     // Ensure sink is stopped before appending
                     sink.stop();
-                    sink.set_volume(self.volume);
-                    sink.append(source);
+                    sink.set_volume(self.output_volume());
+
+                    // Seek the decoder to the start; skipping instead decodes everything
+                    // before it, so only do that for sources that can't seek
+                    let mut skip = Duration::ZERO;
+                    if start > 0.0 {
+                        match source.try_seek(Duration::from_secs_f64(start)) {
+                            Ok(()) => {}
+                            Err(SeekError::NotSupported { .. }) => skip = Duration::from_secs_f64(start),
+                            Err(e) => return Err(anyhow!("Failed to seek to {:.2}s in {}: {}", start, path_str, e)),
+                        }
+                    }
+                    let source = source.skip_duration(skip);
+                    match end {
+                        Some(end) => sink.append(source.take_duration(Duration::from_secs_f64(end - start))),
+                        None => sink.append(source),
+                    }
 
                     // Stop old sink if exists
                     if let Some(old_sink) = &self.sink {
@@ -116,6 +160,7 @@ impl AudioEmitter {
                     self.current_file = Some(path_str);
                     self.state = EmitterState::Stopped;
                     self.position = 0.0;
+                    self.duration = end.map(|end| end - start);
 
                     println!("Successfully loaded audio file: {}", self.current_file.as_ref().unwrap());
                     Ok(())
@@ -235,13 +280,29 @@ impl AudioEmitter {
         self.volume = volume.clamp(0.0, 1.0);
 
         if let Some(sink) = &self.sink {
-            sink.lock().unwrap().set_volume(self.volume);
+            sink.lock().unwrap().set_volume(self.output_volume());
             println!("Volume set to {:.2}", self.volume);
         }
 
         Ok(())
     }
 
+    /// Change the gain offset of the loaded track without reloading it
+    pub fn set_track_gain(&mut self, gain_db: f64) -> Result<()> {
+        self.gain = 10f64.powf(gain_db / 20.0) as f32;
+
+        if let Some(sink) = &self.sink {
+            sink.lock().unwrap().set_volume(self.output_volume());
+        }
+
+        Ok(())
+    }
+
+    /// Volume the sink plays at: the global volume scaled by the track's gain
+    fn output_volume(&self) -> f32 {
+        self.volume * self.gain
+    }
+
     #[allow(dead_code)]
     pub fn seek(&mut self, _position: f64) -> Result<()> {
         // Note: Seeking with rodio is limited. For full seeking support,
@@ -286,6 +347,18 @@ impl AudioEmitter {
                         }
                     }
                 }
+                EmitterCommand::PlayTrack(track) => {
+                    let file_path = track.path.display().to_string();
+                    if let Err(e) = self.load_track(&track) {
+                        eprintln!("Error loading file {}: {}", file_path, e);
+                    } else {
+                        // Small delay to ensure audio is loaded
+                        std::thread::sleep(std::time::Duration::from_millis(50));
+                        if let Err(e) = self.play() {
+                            eprintln!("Error playing file {}: {}", file_path, e);
+                        }
+                    }
+                }
                 EmitterCommand::Pause => {
                     if let Err(e) = self.pause() {
                         eprintln!("Error pausing: {}", e);
@@ -301,6 +374,11 @@ impl AudioEmitter {
                         eprintln!("Error stopping: {}", e);
                     }
                 }
+                EmitterCommand::TrackGain(gain_db) => {
+                    if let Err(e) = self.set_track_gain(gain_db) {
+                        eprintln!("Error setting track gain: {}", e);
+                    }
+                }
                 EmitterCommand::Volume(volume) => {
                     if let Err(e) = self.set_volume(volume) {
                         eprintln!("Error setting volume: {}", e);
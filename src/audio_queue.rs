@@ -1,8 +1,15 @@
 use std::path::{Path, PathBuf};
-use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::cell::OnceCell;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
 use std::fs::{File, self};
-use std::io::{BufReader, BufRead, Write};
+use std::hash::Hasher;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::sync::Mutex;
 use anyhow::{Result, anyhow, Context};
+use chrono::{DateTime, Local, Utc};
 
 use symphonia::core::formats::FormatOptions;
 use symphonia::core::io::MediaSourceStream;
@@ -10,14 +17,59 @@ use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
 use tokio::sync::mpsc::{self, Sender};
 use serde::{Serialize, Deserialize};
+use regex::RegexBuilder;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+use crate::playlist::{self, PlaylistEntry, PlaylistFormat};
+use crate::track_stats::StatsStore;
+use crate::track_tags::{TagFilter, TagStore, TrackTags};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct AudioTrack {
+    /// Stable identifier assigned when the track is added to a queue (0 = unassigned)
+    #[serde(default)]
+    pub id: u64,
     pub path: PathBuf,
     pub title: Option<String>,
     pub artist: Option<String>,
+    #[serde(default)]
+    pub album: Option<String>,
     pub duration: Option<f64>,
     pub position: usize,
+    /// Star rating, see `TagStore`
+    #[serde(default)]
+    pub rating: Option<u8>,
+    #[serde(default)]
+    pub labels: BTreeSet<String>,
+    /// Seconds into the file where playback starts (None = the beginning)
+    #[serde(default)]
+    pub start: Option<f64>,
+    /// Seconds into the file where playback stops (None = the end)
+    #[serde(default)]
+    pub end: Option<f64>,
+    /// Volume offset in dB, applied on top of the global volume while the track plays
+    #[serde(default)]
+    pub gain_db: f64,
+}
+
+impl AudioTrack {
+    /// Length of the part of the file that plays, taking the start and end offsets
+    /// into account. None if the file's duration is unknown and no end is set.
+    pub fn playable_duration(&self) -> Option<f64> {
+        let end = match (self.end, self.duration) {
+            (Some(end), Some(duration)) => end.min(duration),
+            (end, duration) => end.or(duration)?,
+        };
+        Some((end - self.start.unwrap_or(0.0)).max(0.0))
+    }
+
+    pub fn is_trimmed(&self) -> bool {
+        self.start.is_some() || self.end.is_some()
+    }
+
+    /// Linear volume factor of the gain offset
+    pub fn gain_factor(&self) -> f32 {
+        10f64.powf(self.gain_db / 20.0) as f32
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
@@ -27,17 +79,410 @@ pub enum PlaybackState {
     Paused,
 }
 
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, clap::ValueEnum)]
+pub enum RepeatMode {
+    /// Stop at the end of the queue
+    #[default]
+    Off,
+    /// Repeat the current track
+    One,
+    /// Wrap around to the start of the queue
+    All,
+}
+
+/// Track field matched by `AudioQueue::find`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SearchField {
+    Title,
+    Artist,
+    Path,
+    /// Title, artist or path
+    #[default]
+    Any,
+}
+
+/// How the query of `AudioQueue::find` is interpreted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Case-insensitive substring match
+    #[default]
+    Substring,
+    /// Case-insensitive regular expression
+    Regex,
+}
+
+/// Track field or play statistic used by `AudioQueue::sort_by`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum SortKey {
+    Title,
+    Artist,
+    Duration,
+    Path,
+    /// Number of times played
+    Plays,
+    /// Number of times skipped
+    Skips,
+    /// When last played
+    LastPlayed,
+    /// Total time listened
+    Listened,
+    /// Star rating
+    Rating,
+}
+
+impl SortKey {
+    /// Key used to order tracks that are equal on this key
+    fn secondary(self) -> SortKey {
+        match self {
+            SortKey::Title => SortKey::Artist,
+            _ => SortKey::Title,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Comparable value of a sorted field
+#[derive(Debug, PartialEq)]
+enum SortValue {
+    Text(String),
+    Number(f64),
+}
+
+impl Eq for SortValue {}
+
+impl Ord for SortValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (SortValue::Text(a), SortValue::Text(b)) => a.cmp(b),
+            (SortValue::Number(a), SortValue::Number(b)) => a.total_cmp(b),
+            (SortValue::Text(_), SortValue::Number(_)) => Ordering::Less,
+            (SortValue::Number(_), SortValue::Text(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for SortValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// What `AudioQueue::add_track` does when the added file is already queued
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Queue the file again
+    #[default]
+    Allow,
+    /// Keep the queued copy and drop the new one
+    Skip,
+    /// Move the queued copy to where the new one would have been added
+    MoveExisting,
+}
+
+/// Result of `AudioQueue::add_track`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddOutcome {
+    /// The track was inserted at this position
+    Added(usize),
+    /// The file is already queued at this position, nothing changed
+    Skipped(usize),
+    /// The queued copy was moved instead of adding the file again
+    Moved { from: usize, to: usize },
+}
+
+/// Identity of a queued file section for duplicate detection. Different sections of
+/// one file, e.g. tracks of a cue sheet, are not duplicates. The content hash is only
+/// computed for files of equal size, and at most once.
+#[derive(Debug)]
+struct FileIdentity {
+    /// Path of the track as queued, before canonicalizing
+    track_path: PathBuf,
+    path: PathBuf,
+    section: (Option<f64>, Option<f64>),
+    size: Option<u64>,
+    hash: OnceCell<Option<u64>>,
+}
+
+impl FileIdentity {
+    fn new(track: &AudioTrack, match_content: bool) -> Self {
+        let path = track.path.as_path();
+        Self {
+            track_path: track.path.clone(),
+            path: canonical_path(path),
+            section: (track.start, track.end),
+            size: if match_content {
+                fs::metadata(path).ok().map(|metadata| metadata.len())
+            } else {
+                None
+            },
+            hash: OnceCell::new(),
+        }
+    }
+
+    /// Whether this identity was built from `track`'s current path and section
+    fn describes(&self, track: &AudioTrack) -> bool {
+        self.track_path == track.path && self.section == (track.start, track.end)
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        *self.hash.get_or_init(|| {
+            let mut file = File::open(&self.path).ok()?;
+            let mut hasher = DefaultHasher::new();
+            let mut buffer = [0u8; 64 * 1024];
+            loop {
+                match file.read(&mut buffer).ok()? {
+                    0 => break,
+                    read => hasher.write(&buffer[..read]),
+                }
+            }
+            Some(hasher.finish())
+        })
+    }
+
+    fn matches(&self, other: &FileIdentity) -> bool {
+        if self.section != other.section {
+            return false;
+        }
+        if self.path == other.path {
+            return true;
+        }
+
+        match (self.size, other.size) {
+            (Some(a), Some(b)) if a == b => {
+                self.content_hash().is_some() && self.content_hash() == other.content_hash()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Maximum number of entries kept in the playback history
+pub const MAX_HISTORY_ENTRIES: usize = 100;
+
+/// A track that was actually played, and when it started
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HistoryEntry {
+    pub track: AudioTrack,
+    pub played_at: DateTime<Utc>,
+}
+
+/// How far playback has got into the track with `track_id`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PlaybackClock {
+    pub track_id: u64,
+    /// Seconds played before `resumed_at`
+    pub elapsed: f64,
+    /// When playback last started or resumed, None while paused
+    pub resumed_at: Option<DateTime<Utc>>,
+}
+
+/// Durations of the queue and estimated start times of upcoming tracks, in seconds
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct QueueTiming {
+    /// Known length of all queued tracks, including the up-next lane
+    pub total: f64,
+    /// Known time left, from the playback position in the current track
+    pub remaining: f64,
+    /// Playback position in the current track
+    pub elapsed: f64,
+    /// Number of tracks with unknown duration, which the sums leave out
+    pub unknown_durations: usize,
+    /// Tracks after the current one in play order, for a single pass through the queue
+    pub upcoming: Vec<TrackEta>,
+}
+
+/// Estimated start of an upcoming track
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TrackEta {
+    pub id: u64,
+    /// Position in the queue, None for tracks in the up-next lane
+    pub position: Option<usize>,
+    pub duration: Option<f64>,
+    /// None when a track of unknown duration plays before this one
+    pub starts_at: Option<DateTime<Utc>>,
+}
+
+/// Canonical form of a file path, or the path itself if it can't be resolved
+pub fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Format seconds as `M:SS`, or `H:MM:SS` from one hour on
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Parse a time offset written as seconds (`90`, `12.5`), `M:SS` or `H:MM:SS(.f)`
+pub fn parse_timestamp(text: &str) -> Result<f64> {
+    let invalid = || anyhow!("Invalid time '{}', expected seconds, M:SS or H:MM:SS", text);
+
+    let parts: Vec<&str> = text.trim().split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0.0;
+    for (index, part) in parts.iter().enumerate() {
+        let value: f64 = part.parse().map_err(|_| invalid())?;
+        let is_last = index == parts.len() - 1;
+        // Only the last field may be fractional, and fields after the first stay below 60
+        if !value.is_finite() || value < 0.0 || (!is_last && value.fract() != 0.0)
+            || (index > 0 && value >= 60.0) {
+            return Err(invalid());
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(seconds)
+}
+
+/// Check that `start` and `end` describe a non-empty section of a file lasting `duration`
+pub fn validate_trim(start: Option<f64>, end: Option<f64>, duration: Option<f64>) -> Result<()> {
+    let first = start.unwrap_or(0.0);
+    if first < 0.0 {
+        return Err(anyhow!("Start cannot be negative"));
+    }
+    if let Some(end) = end {
+        if end <= first {
+            return Err(anyhow!("End ({}) must come after start ({})", format_duration(end), format_duration(first)));
+        }
+    }
+    if let Some(duration) = duration {
+        if first >= duration {
+            return Err(anyhow!("Start ({}) is past the end of the file ({})", format_duration(first), format_duration(duration)));
+        }
+    }
+    Ok(())
+}
+
+/// Largest gain offset, up or down, that a track can have
+pub const MAX_GAIN_DB: f64 = 24.0;
+
+/// Maximum number of queue changes that can be undone
+pub const MAX_JOURNAL_DEPTH: usize = 50;
+
+/// A recorded queue change. Undo applies its inverse, redo applies it again.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum QueueOperation {
+    /// `track` was inserted at `position`
+    Add { position: usize, track: AudioTrack },
+    /// `track` was removed from `position`
+    Remove { position: usize, track: AudioTrack, was_current: bool },
+    /// A track was moved from `from` to `to`
+    Move { from: usize, to: usize },
+    /// The whole track list was replaced, e.g. by `clear`
+    Replace {
+        before: Vec<AudioTrack>,
+        before_position: Option<usize>,
+        after: Vec<AudioTrack>,
+        after_position: Option<usize>,
+    },
+    /// `track` was added to the end of the "play next" lane
+    AddNext { track: AudioTrack },
+    /// The first lane track was inserted at `position` and made current,
+    /// replacing `previous_position` as the current track
+    TakeNext { position: usize, track: AudioTrack, previous_position: Option<usize> },
+    /// The tracks at `positions` were moved to the end of the "play next" lane
+    Promote { positions: Vec<usize>, tracks: Vec<AudioTrack> },
+}
+
+impl std::fmt::Display for QueueOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueOperation::Add { position, track } => write!(f, "add {} at {}", track, position),
+            QueueOperation::Remove { position, track, .. } => write!(f, "remove {} from {}", track, position),
+            QueueOperation::Move { from, to } => write!(f, "move {} to {}", from, to),
+            QueueOperation::Replace { before, after, .. } => {
+                write!(f, "replace {} tracks with {}", before.len(), after.len())
+            }
+            QueueOperation::AddNext { track } => write!(f, "add {} to play next", track),
+            QueueOperation::TakeNext { position, track, .. } => write!(f, "play {} next at {}", track, position),
+            QueueOperation::Promote { tracks, .. } => write!(f, "move {} tracks to play next", tracks.len()),
+        }
+    }
+}
+
+/// Bounded undo/redo stacks of queue changes
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct OperationJournal {
+    undo: Vec<QueueOperation>,
+    redo: Vec<QueueOperation>,
+}
+
+impl OperationJournal {
+    /// Record a new change. Any undone changes can no longer be redone.
+    fn record(&mut self, operation: QueueOperation) {
+        self.redo.clear();
+        self.undo.push(operation);
+        if self.undo.len() > MAX_JOURNAL_DEPTH {
+            let excess = self.undo.len() - MAX_JOURNAL_DEPTH;
+            self.undo.drain(..excess);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AudioQueueState {
     pub tracks: Vec<AudioTrack>,
     pub current_position: Option<usize>,
     pub playback_state: PlaybackState,
+    #[serde(default)]
+    pub shuffle: bool,
+    #[serde(default)]
+    pub shuffle_order: Vec<usize>,
+    #[serde(default)]
+    pub repeat: RepeatMode,
+    #[serde(default)]
+    pub next_id: u64,
+    #[serde(default)]
+    pub history: Vec<HistoryEntry>,
+    #[serde(default)]
+    pub journal: OperationJournal,
+    #[serde(default)]
+    pub up_next: Vec<AudioTrack>,
+    #[serde(default)]
+    pub duplicate_policy: DuplicatePolicy,
+    #[serde(default)]
+    pub match_content: bool,
+    #[serde(default)]
+    pub clock: Option<PlaybackClock>,
+    #[serde(default)]
+    pub stop_after: Option<u64>,
+    #[serde(default)]
+    pub consume: bool,
 }
 
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub enum QueueCommand {
     Add(AudioTrack, Option<usize>),
+    AddNext(AudioTrack),
+    PlayNext(Vec<usize>),
+    Sort(SortKey, SortOrder),
     Remove(usize),
     Move(usize, usize),
     Play,
@@ -48,6 +493,32 @@ pub enum QueueCommand {
     Jump(usize),
     Clear,
     GetStatus,
+    Undo,
+    Redo,
+    SetShuffle(bool),
+    SetRepeat(RepeatMode),
+    RemoveById(u64),
+    MoveById(u64, usize),
+    JumpById(u64),
+    SetDuplicatePolicy(DuplicatePolicy, bool),
+    SetTags(PathBuf, TrackTags),
+    AddTracks(Vec<AudioTrack>),
+    /// Replace the queue with these tracks, as one step that can be undone
+    ReplaceTracks(Vec<AudioTrack>),
+    Restore(Box<AudioQueueState>),
+    Dedupe,
+    RemovePositions(Vec<usize>),
+    RemoveRange(Range<usize>),
+    KeepRange(Range<usize>),
+    MoveBlock(Range<usize>, usize),
+    /// Start and end offsets in seconds for the track at a position
+    SetTrim(usize, Option<f64>, Option<f64>),
+    /// Gain offset in dB for the track at a position
+    SetGain(usize, f64),
+    /// Stop after the track with this ID finishes, or never with None
+    SetStopAfter(Option<u64>),
+    /// Remove tracks from the queue once they finish or are skipped
+    SetConsume(bool),
 }
 
 #[derive(Debug)]
@@ -56,6 +527,27 @@ pub struct AudioQueue {
     pub current_position: Option<usize>,
     pub playback_state: PlaybackState,
     command_sender: Option<Sender<QueueCommand>>,
+    shuffle: bool,
+    /// Play order used while shuffle is enabled, as indices into `tracks`
+    shuffle_order: Vec<usize>,
+    repeat: RepeatMode,
+    next_id: u64,
+    /// Played tracks, oldest first. The last entry is the track playing now.
+    history: Vec<HistoryEntry>,
+    journal: OperationJournal,
+    /// "Play next" lane, played first-in-first-out right after the current track
+    up_next: VecDeque<AudioTrack>,
+    duplicate_policy: DuplicatePolicy,
+    /// Also treat files with identical content as duplicates
+    match_content: bool,
+    clock: Option<PlaybackClock>,
+    /// ID of the track after which playback stops instead of advancing
+    stop_after: Option<u64>,
+    /// Remove tracks once they finish or are skipped
+    consume: bool,
+    /// File identities of queued tracks by ID, so duplicate checks don't canonicalize
+    /// and hash every queued file again on each add
+    identities: Mutex<HashMap<u64, FileIdentity>>,
 }
 
 impl std::fmt::Display for AudioTrack {
@@ -63,7 +555,7 @@ impl std::fmt::Display for AudioTrack {
         write!(f, "{} - {} ({:.1}s)",
             self.title.as_deref().unwrap_or("Unknown"),
             self.artist.as_deref().unwrap_or("Unknown Artist"),
-            self.duration.unwrap_or(0.0)
+            self.playable_duration().unwrap_or(0.0)
         )
     }
 }
@@ -76,6 +568,101 @@ impl AudioQueue {
             current_position: None,
             playback_state: PlaybackState::Stopped,
             command_sender: None,
+            shuffle: false,
+            shuffle_order: Vec::new(),
+            repeat: RepeatMode::Off,
+            next_id: 1,
+            history: Vec::new(),
+            journal: OperationJournal::default(),
+            up_next: VecDeque::new(),
+            duplicate_policy: DuplicatePolicy::Allow,
+            match_content: false,
+            clock: None,
+            stop_after: None,
+            consume: false,
+            identities: Mutex::default(),
+        }
+    }
+
+    /// Build a queue from a previously saved state
+    pub fn from_state(state: AudioQueueState) -> Self {
+        let mut queue = Self {
+            tracks: state.tracks.into(),
+            current_position: state.current_position,
+            playback_state: state.playback_state,
+            command_sender: None,
+            shuffle: state.shuffle,
+            shuffle_order: state.shuffle_order,
+            repeat: state.repeat,
+            next_id: state.next_id,
+            history: state.history,
+            journal: state.journal,
+            up_next: state.up_next.into(),
+            duplicate_policy: state.duplicate_policy,
+            match_content: state.match_content,
+            clock: state.clock,
+            stop_after: state.stop_after,
+            consume: state.consume,
+            identities: Mutex::default(),
+        };
+        queue.update_positions();
+        queue.ensure_track_ids();
+
+        // Regenerate the shuffle order if the saved one no longer matches the tracks
+        if queue.shuffle && !queue.is_valid_shuffle_order() {
+            queue.reshuffle();
+        }
+
+        queue
+    }
+
+    /// Replace the whole queue with a saved state. The command sender, journal and
+    /// play history are kept, and the change of tracks is journaled so it can be undone.
+    pub fn restore(&mut self, state: AudioQueueState) {
+        let before: Vec<AudioTrack> = self.tracks.iter().cloned().collect();
+        let before_position = self.current_position;
+        let command_sender = self.command_sender.take();
+        let journal = std::mem::take(&mut self.journal);
+        let history = std::mem::take(&mut self.history);
+        let clock = self.clock.take();
+        let next_id = self.next_id;
+
+        *self = Self::from_state(state);
+        self.command_sender = command_sender;
+        self.journal = journal;
+        self.history = history;
+        // The saved clock is stale; the running one still holds if the same track is current
+        let current_id = self.get_current_track().map(|track| track.id);
+        self.clock = clock.filter(|clock| Some(clock.track_id) == current_id);
+        // IDs of tracks in the journal must not be handed out again
+        self.next_id = self.next_id.max(next_id);
+
+        self.journal.record(QueueOperation::Replace {
+            before,
+            before_position,
+            after: self.tracks.iter().cloned().collect(),
+            after_position: self.current_position,
+        });
+    }
+
+    /// Capture the current queue as a serializable state
+    pub fn to_state(&self) -> AudioQueueState {
+        AudioQueueState {
+            tracks: self.tracks.iter().cloned().collect(),
+            current_position: self.current_position,
+            playback_state: self.playback_state,
+            shuffle: self.shuffle,
+            shuffle_order: self.shuffle_order.clone(),
+            repeat: self.repeat,
+            next_id: self.next_id,
+            history: self.history.clone(),
+            journal: self.journal.clone(),
+            up_next: self.up_next.iter().cloned().collect(),
+            duplicate_policy: self.duplicate_policy,
+            match_content: self.match_content,
+            clock: self.clock.clone(),
+            stop_after: self.stop_after,
+            consume: self.consume,
         }
     }
 
@@ -89,12 +676,9 @@ impl AudioQueue {
                 .context("Failed to parse queue state file")?;
 
             let (tx, _) = mpsc::channel(100);
-            Ok(Self {
-                tracks: state.tracks.into(),
-                current_position: state.current_position,
-                playback_state: state.playback_state,
-                command_sender: Some(tx),
-            })
+            let mut queue = Self::from_state(state);
+            queue.command_sender = Some(tx);
+            Ok(queue)
         } else {
             Ok(Self::new())
         }
@@ -103,11 +687,7 @@ impl AudioQueue {
     /// Save current state to file
     pub fn save_state<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let path = path.as_ref();
-        let state = AudioQueueState {
-            tracks: self.tracks.iter().cloned().collect(),
-            current_position: self.current_position,
-            playback_state: self.playback_state,
-        };
+        let state = self.to_state();
 
         let content = serde_json::to_string_pretty(&state)
             .context("Failed to serialize queue state")?;
@@ -195,6 +775,7 @@ impl AudioQueue {
 
         let mut title = None;
         let mut artist = None;
+        let mut album = None;
         let mut duration = None;
 
         // Try to get metadata from all metadata revisions
@@ -204,6 +785,7 @@ impl AudioQueue {
                     // Standard tag names
                     "TITLE" | "TIT2" => title = Some(tag.value.to_string()),
                     "ARTIST" | "TPE1" => artist = Some(tag.value.to_string()),
+                    "ALBUM" | "TALB" => album = Some(tag.value.to_string()),
                     // Alternative tag names
                     "TITLE\x00" => title = Some(tag.value.to_string()),
                     "ARTIST\x00" => artist = Some(tag.value.to_string()),
@@ -253,200 +835,1401 @@ impl AudioQueue {
             path: absolute_path,
             title,
             artist,
+            album,
             duration,
             position: 0,
+            ..Default::default()
         })
     }
 
-    pub fn add_track(&mut self, mut track: AudioTrack, position: Option<usize>) -> Result<()> {
-        match position {
-            Some(pos) => {
-                if pos > self.tracks.len() {
-                    return Err(anyhow!("Position {} is out of bounds", pos));
+    /// Add a track at `position` (the end by default), applying the duplicate policy
+    /// if the file is already queued
+    pub fn add_track(&mut self, mut track: AudioTrack, position: Option<usize>) -> Result<AddOutcome> {
+        let position = position.unwrap_or(self.tracks.len());
+        if position > self.tracks.len() {
+            return Err(anyhow!("Position {} is out of bounds", position));
+        }
+
+        if self.duplicate_policy != DuplicatePolicy::Allow {
+            if let Some(existing) = self.find_duplicate(&track) {
+                if self.duplicate_policy == DuplicatePolicy::Skip {
+                    return Ok(AddOutcome::Skipped(existing));
                 }
-                track.position = pos;
-                self.tracks.insert(pos, track);
-            }
-            None => {
-                track.position = self.tracks.len();
-                self.tracks.push_back(track);
+
+                // `position` counts the existing copy, which is taken out before reinserting
+                let to = if existing < position { position - 1 } else { position };
+                let to = to.min(self.tracks.len() - 1);
+                self.move_track(existing, to)?;
+                return Ok(AddOutcome::Moved { from: existing, to });
             }
         }
 
-        self.update_positions();
-        Ok(())
+        // Every added track gets a fresh ID, even if it came from another queue
+        track.id = self.next_id;
+        self.next_id += 1;
+
+        self.insert_raw(position, track.clone());
+        self.journal.record(QueueOperation::Add { position, track });
+        Ok(AddOutcome::Added(position))
     }
 
-    fn update_positions(&mut self) {
-        for (index, track) in self.tracks.iter_mut().enumerate() {
-            track.position = index;
-        }
+    /// Append `tracks` as one journaled operation, applying the duplicate policy.
+    /// Returns the number of tracks added.
+    pub fn add_tracks(&mut self, tracks: Vec<AudioTrack>) -> usize {
+        let current = self.current_position;
+        self.load_tracks(self.tracks.iter().cloned().collect(), current, tracks)
     }
 
-    pub fn remove_track(&mut self, position: usize) -> Result<()> {
-        if position >= self.tracks.len() {
-            return Err(anyhow!("Position {} is out of bounds", position));
-        }
+    /// Replace the queue with `tracks` as one journaled operation, so loading a long
+    /// playlist can't push the old queue out of the journal. Returns the number of
+    /// tracks added.
+    pub fn replace_tracks(&mut self, tracks: Vec<AudioTrack>) -> usize {
+        self.up_next.clear();
+        self.stop_after = None;
+        self.load_tracks(Vec::new(), None, tracks)
+    }
 
-        // If removing the current track, update position
-        if let Some(current_pos) = self.current_position {
-            if current_pos == position {
-                if self.tracks.is_empty() {
-                    self.current_position = None;
-                    self.playback_state = PlaybackState::Stopped;
-                } else if current_pos >= self.tracks.len() {
-                    self.current_position = Some(self.tracks.len().saturating_sub(1));
+    /// Record replacing the track list with `base` plus `tracks`, where `current` is
+    /// the current position in `base`
+    fn load_tracks(&mut self, mut after: Vec<AudioTrack>, current: Option<usize>, tracks: Vec<AudioTrack>) -> usize {
+        let before: Vec<AudioTrack> = self.tracks.iter().cloned().collect();
+        let before_position = self.current_position;
+        let current_id = current.and_then(|current| after.get(current)).map(|track| track.id);
+
+        drop(self.refresh_identities());
+        let mut identities = self.identities.lock().unwrap();
+        let mut added = 0;
+        for mut track in tracks {
+            let identity = FileIdentity::new(&track, self.match_content);
+            if self.duplicate_policy != DuplicatePolicy::Allow {
+                if let Some(existing) = after.iter().position(|queued| identity.matches(&identities[&queued.id])) {
+                    // Like `add_track`, moving puts the existing copy where the new one would go
+                    if self.duplicate_policy == DuplicatePolicy::MoveExisting {
+                        let moved = after.remove(existing);
+                        after.push(moved);
+                    }
+                    continue;
                 }
-            } else if current_pos > position {
-                self.current_position = Some(current_pos - 1);
             }
-        }
-
-        self.tracks.remove(position);
-        self.update_positions();
-        Ok(())
-    }
 
-    pub fn move_track(&mut self, from: usize, to: usize) -> Result<()> {
-        if from >= self.tracks.len() || to >= self.tracks.len() {
-            return Err(anyhow!("Positions are out of bounds"));
+            track.id = self.next_id;
+            self.next_id += 1;
+            identities.insert(track.id, identity);
+            after.push(track);
+            added += 1;
         }
+        drop(identities);
 
-        if from == to {
-            return Ok(());
+        let after_position = current_id
+            .and_then(|id| after.iter().position(|track| track.id == id));
+        if after == before && after_position == before_position {
+            return added;
         }
+        self.replace_raw(after.clone(), after_position);
+        self.journal.record(QueueOperation::Replace {
+            before,
+            before_position,
+            after,
+            after_position,
+        });
+        added
+    }
 
-        let track = self.tracks.remove(from).unwrap();
-        self.tracks.insert(to, track);
-        self.update_positions();
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
 
-        // Update current position if needed
-        if let Some(current) = self.current_position {
-            if current == from {
-                self.current_position = Some(to);
-            } else if from < to && current <= to {
-                self.current_position = Some(current - 1);
-            } else if from > to && current >= to {
-                self.current_position = Some(current + 1);
-            }
-        }
+    pub fn matches_content(&self) -> bool {
+        self.match_content
+    }
 
-        Ok(())
+    /// Set how `add_track` handles files that are already queued. With
+    /// `match_content`, files with identical content count as the same file.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy, match_content: bool) {
+        self.duplicate_policy = policy;
+        self.match_content = match_content;
+        self.identities.get_mut().unwrap().clear();
     }
 
-    pub fn play(&mut self) -> Result<()> {
-        if self.tracks.is_empty() {
-            return Err(anyhow!("Queue is empty"));
+    /// Cached identities brought in line with the queued tracks: the missing and
+    /// outdated ones are built and those of tracks that left the queue dropped
+    fn refresh_identities(&self) -> std::sync::MutexGuard<'_, HashMap<u64, FileIdentity>> {
+        let mut identities = self.identities.lock().unwrap();
+        for track in &self.tracks {
+            if !identities.get(&track.id).is_some_and(|identity| identity.describes(track)) {
+                identities.insert(track.id, FileIdentity::new(track, self.match_content));
+            }
         }
-
-        if self.current_position.is_none() {
-            self.current_position = Some(0);
+        if identities.len() > self.tracks.len() {
+            let queued: HashSet<u64> = self.tracks.iter().map(|track| track.id).collect();
+            identities.retain(|id, _| queued.contains(id));
         }
+        identities
+    }
 
-        self.playback_state = PlaybackState::Playing;
-        Ok(())
+    /// Position of a queued track that refers to the same file as `track`
+    pub fn find_duplicate(&self, track: &AudioTrack) -> Option<usize> {
+        let identities = self.refresh_identities();
+        let identity = FileIdentity::new(track, self.match_content);
+        self.tracks.iter()
+            .position(|queued| identity.matches(&identities[&queued.id]))
     }
 
-    pub fn pause(&mut self) -> Result<()> {
-        if self.current_position.is_none() {
-            return Err(anyhow!("No track is currently selected"));
+    /// Positions of tracks that repeat a file queued elsewhere. The first copy of
+    /// each file is kept, unless a later copy is the current track.
+    pub fn duplicate_positions(&self) -> Vec<usize> {
+        let cache = self.refresh_identities();
+        let identities: Vec<&FileIdentity> = self.tracks.iter()
+            .map(|track| &cache[&track.id])
+            .collect();
+
+        // Index of the copy that is kept for each track's file
+        let mut kept: Vec<usize> = Vec::new();
+        let mut duplicates = Vec::new();
+        for (index, identity) in identities.iter().enumerate() {
+            match kept.iter().position(|&k| identities[k].matches(identity)) {
+                None => kept.push(index),
+                Some(slot) if self.current_position == Some(index) => {
+                    duplicates.push(kept[slot]);
+                    kept[slot] = index;
+                }
+                Some(_) => duplicates.push(index),
+            }
         }
 
-        self.playback_state = PlaybackState::Paused;
-        Ok(())
+        duplicates.sort_unstable();
+        duplicates
     }
 
-    pub fn resume(&mut self) -> Result<()> {
-        if self.current_position.is_none() {
-            return Err(anyhow!("No track is currently selected"));
-        }
+    /// Remove duplicate tracks, see `duplicate_positions`. Returns the removed tracks.
+    pub fn dedupe(&mut self) -> Vec<AudioTrack> {
+        let duplicates = self.duplicate_positions();
+        self.remove_positions(&duplicates)
+            .expect("duplicate positions are in bounds")
+    }
 
-        if matches!(self.playback_state, PlaybackState::Paused) {
-            self.playback_state = PlaybackState::Playing;
-        } else {
-            self.play()?;
-        }
+    /// Add a track to the "play next" lane. Lane tracks play right after the
+    /// current track, before the rest of the queue, in the order they were added.
+    pub fn add_next(&mut self, mut track: AudioTrack) {
+        track.id = self.next_id;
+        self.next_id += 1;
+        self.up_next.push_back(track.clone());
+        self.journal.record(QueueOperation::AddNext { track });
+    }
 
-        Ok(())
+    pub fn get_up_next(&self) -> &VecDeque<AudioTrack> {
+        &self.up_next
     }
 
-    pub fn next_track(&mut self) -> Result<()> {
-        if self.tracks.is_empty() {
-            return Err(anyhow!("Queue is empty"));
-        }
+    /// Move the first lane track into the queue right after the current track
+    /// and make it current
+    fn take_up_next(&mut self) -> bool {
+        let Some(track) = self.up_next.pop_front() else {
+            return false;
+        };
 
-        if let Some(current) = self.current_position {
-            if current < self.tracks.len() - 1 {
-                self.current_position = Some(current + 1);
-                Ok(())
-            } else {
-                Err(anyhow!("Already at last track"))
-            }
-        } else {
-            self.current_position = Some(0);
-            Ok(())
-        }
+        let previous_position = self.current_position;
+        let position = self.play_next_raw(track.clone());
+        self.journal.record(QueueOperation::TakeNext { position, track, previous_position });
+        true
     }
 
-    pub fn previous(&mut self) -> Result<()> {
-        if self.tracks.is_empty() {
-            return Err(anyhow!("Queue is empty"));
+    /// Insert a lane track right after the current track and make it current,
+    /// without touching the journal. Returns its position.
+    fn play_next_raw(&mut self, track: AudioTrack) -> usize {
+        let position = self.current_position.map_or(0, |current| current + 1);
+        self.insert_raw(position, track);
+
+        // The lane track must play next even when shuffled
+        if self.shuffle {
+            self.shuffle_order.retain(|&entry| entry != position);
+            let slot = self.current_position
+                .and_then(|current| self.shuffle_order.iter().position(|&p| p == current))
+                .map_or(0, |slot| slot + 1);
+            self.shuffle_order.insert(slot, position);
         }
 
-        if let Some(current) = self.current_position {
-            if current > 0 {
-                self.current_position = Some(current - 1);
-                Ok(())
-            } else {
-                Err(anyhow!("Already at first track"))
+        self.current_position = Some(position);
+        position
+    }
+
+    /// Give every track a unique non-zero ID, e.g. after loading an older state file
+    fn ensure_track_ids(&mut self) {
+        let highest = self.tracks.iter().chain(self.up_next.iter())
+            .map(|track| track.id)
+            .max()
+            .unwrap_or(0);
+        self.next_id = self.next_id.max(highest + 1);
+
+        let mut seen = HashSet::new();
+        for track in self.tracks.iter_mut().chain(self.up_next.iter_mut()) {
+            if track.id == 0 || !seen.insert(track.id) {
+                track.id = self.next_id;
+                self.next_id += 1;
+                seen.insert(track.id);
             }
-        } else {
-            self.current_position = Some(0);
-            Ok(())
         }
     }
 
-    pub fn jump_to(&mut self, position: usize) -> Result<()> {
-        if position >= self.tracks.len() {
-            return Err(anyhow!("Position {} is out of bounds", position));
-        }
+    /// Current position of the track with the given ID
+    pub fn position_of(&self, id: u64) -> Option<usize> {
+        self.tracks.iter().position(|track| track.id == id)
+    }
 
-        self.current_position = Some(position);
-        Ok(())
+    pub fn get_track_by_id(&self, id: u64) -> Option<&AudioTrack> {
+        self.tracks.iter().find(|track| track.id == id)
     }
 
-    pub fn clear(&mut self) -> Result<()> {
-        self.tracks.clear();
-        self.current_position = None;
-        self.playback_state = PlaybackState::Stopped;
-        Ok(())
+    pub fn remove_track_by_id(&mut self, id: u64) -> Result<()> {
+        let position = self.position_of(id)
+            .ok_or_else(|| anyhow!("No track with ID {}", id))?;
+        self.remove_track(position)
     }
 
-    pub fn get_queue(&self) -> &VecDeque<AudioTrack> {
-        &self.tracks
+    pub fn move_track_by_id(&mut self, id: u64, to: usize) -> Result<()> {
+        let from = self.position_of(id)
+            .ok_or_else(|| anyhow!("No track with ID {}", id))?;
+        self.move_track(from, to)
     }
 
-    pub fn get_current_track(&self) -> Option<&AudioTrack> {
-        self.current_position.and_then(|pos| self.tracks.get(pos))
+    pub fn jump_to_id(&mut self, id: u64) -> Result<()> {
+        let position = self.position_of(id)
+            .ok_or_else(|| anyhow!("No track with ID {}", id))?;
+        self.jump_to(position)
     }
 
-    pub fn get_status(&self) -> (PlaybackState, Option<AudioTrack>, usize) {
-        (
-            self.playback_state,
-            self.get_current_track().cloned(),
-            self.tracks.len(),
-        )
+    fn update_positions(&mut self) {
+        for (index, track) in self.tracks.iter_mut().enumerate() {
+            track.position = index;
+        }
     }
 
-    pub fn display_queue(&self) -> String {
+    pub fn remove_track(&mut self, position: usize) -> Result<()> {
+        if position >= self.tracks.len() {
+            return Err(anyhow!("Position {} is out of bounds", position));
+        }
+
+        let was_current = self.current_position == Some(position);
+        let track = self.remove_raw(position);
+        self.journal.record(QueueOperation::Remove { position, track, was_current });
+        Ok(())
+    }
+
+    pub fn move_track(&mut self, from: usize, to: usize) -> Result<()> {
+        if from >= self.tracks.len() || to >= self.tracks.len() {
+            return Err(anyhow!("Positions are out of bounds"));
+        }
+
+        if from == to {
+            return Ok(());
+        }
+
+        self.move_raw(from, to);
+        self.journal.record(QueueOperation::Move { from, to });
+        Ok(())
+    }
+
+    /// Remove the tracks at `positions` in one step. If the current track is
+    /// removed, the first remaining track after it becomes current.
+    pub fn remove_positions(&mut self, positions: &[usize]) -> Result<Vec<AudioTrack>> {
+        if let Some(&position) = positions.iter().find(|&&p| p >= self.tracks.len()) {
+            return Err(anyhow!("Position {} is out of bounds", position));
+        }
+
+        let removed_set: HashSet<usize> = positions.iter().copied().collect();
+        if removed_set.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let before: Vec<AudioTrack> = self.tracks.iter().cloned().collect();
+        let before_position = self.current_position;
+
+        let mut removed = Vec::new();
+        let mut after = Vec::new();
+        for (index, track) in before.iter().enumerate() {
+            if removed_set.contains(&index) {
+                removed.push(track.clone());
+            } else {
+                after.push(track.clone());
+            }
+        }
+
+        // Tracks kept before the current one give its new index, whether or not it survived
+        let after_position = before_position
+            .map(|current| (0..current).filter(|index| !removed_set.contains(index)).count())
+            .filter(|_| !after.is_empty())
+            .map(|current| current.min(after.len() - 1));
+
+        self.replace_raw(after.clone(), after_position);
+        self.journal.record(QueueOperation::Replace {
+            before,
+            before_position,
+            after,
+            after_position,
+        });
+
+        Ok(removed)
+    }
+
+    /// Remove the tracks in `range`
+    pub fn remove_range(&mut self, range: Range<usize>) -> Result<Vec<AudioTrack>> {
+        self.check_range(&range)?;
+        self.remove_positions(&range.collect::<Vec<_>>())
+    }
+
+    /// Remove every track outside `range`
+    pub fn keep_range(&mut self, range: Range<usize>) -> Result<Vec<AudioTrack>> {
+        self.check_range(&range)?;
+        let outside: Vec<usize> = (0..self.tracks.len())
+            .filter(|index| !range.contains(index))
+            .collect();
+        self.remove_positions(&outside)
+    }
+
+    /// Move the tracks in `range` as one block so that it starts at `to`, counted
+    /// after the block is taken out of the queue. The current track stays current.
+    pub fn move_block(&mut self, range: Range<usize>, to: usize) -> Result<()> {
+        self.check_range(&range)?;
+        if to + range.len() > self.tracks.len() {
+            return Err(anyhow!("Position {} is out of bounds for a block of {} tracks", to, range.len()));
+        }
+        if to == range.start {
+            return Ok(());
+        }
+
+        let before: Vec<AudioTrack> = self.tracks.iter().cloned().collect();
+        let before_position = self.current_position;
+        let current_id = self.get_current_track().map(|track| track.id);
+
+        let mut after = before.clone();
+        let block: Vec<AudioTrack> = after.drain(range).collect();
+        after.splice(to..to, block);
+
+        let after_position = current_id
+            .and_then(|id| after.iter().position(|track| track.id == id));
+        self.replace_raw(after.clone(), after_position);
+        self.journal.record(QueueOperation::Replace {
+            before,
+            before_position,
+            after,
+            after_position,
+        });
+
+        Ok(())
+    }
+
+    fn check_range(&self, range: &Range<usize>) -> Result<()> {
+        if range.is_empty() {
+            return Err(anyhow!("Range {}..{} is empty", range.start, range.end));
+        }
+        if range.end > self.tracks.len() {
+            return Err(anyhow!("Range {}..{} is out of bounds", range.start, range.end));
+        }
+        Ok(())
+    }
+
+    /// Insert a track without touching the journal or its ID
+    fn insert_raw(&mut self, position: usize, mut track: AudioTrack) {
+        track.position = position;
+        self.tracks.insert(position, track);
+
+        if let Some(current) = self.current_position {
+            if current >= position {
+                self.current_position = Some(current + 1);
+            }
+        }
+
+        self.shuffle_insert(position);
+        self.update_positions();
+    }
+
+    /// Remove a track without touching the journal, keeping the current position valid
+    fn remove_raw(&mut self, position: usize) -> AudioTrack {
+        let track = self.tracks.remove(position).expect("position checked by caller");
+        self.shuffle_remove(position);
+
+        if let Some(current) = self.current_position {
+            if self.tracks.is_empty() {
+                self.current_position = None;
+                self.playback_state = PlaybackState::Stopped;
+            } else if current > position {
+                self.current_position = Some(current - 1);
+            } else if current == position && current >= self.tracks.len() {
+                // The last track was current, fall back to the new last track
+                self.current_position = Some(self.tracks.len() - 1);
+            }
+        }
+
+        self.update_positions();
+        self.drop_orphaned_stop_marker();
+        track
+    }
+
+    /// Remove the stop marker once its track has left both the queue and the lane
+    fn drop_orphaned_stop_marker(&mut self) {
+        if let Some(id) = self.stop_after {
+            if !self.tracks.iter().chain(self.up_next.iter()).any(|track| track.id == id) {
+                self.stop_after = None;
+            }
+        }
+    }
+
+    /// Move a track without touching the journal, keeping the current track selected
+    fn move_raw(&mut self, from: usize, to: usize) {
+        let track = self.tracks.remove(from).expect("position checked by caller");
+        self.tracks.insert(to, track);
+        self.shuffle_move(from, to);
+        self.update_positions();
+
+        if let Some(current) = self.current_position {
+            if current == from {
+                self.current_position = Some(to);
+            } else if from < to && current > from && current <= to {
+                self.current_position = Some(current - 1);
+            } else if from > to && current >= to && current < from {
+                self.current_position = Some(current + 1);
+            }
+        }
+    }
+
+    /// Replace the whole track list without touching the journal
+    fn replace_raw(&mut self, tracks: Vec<AudioTrack>, current_position: Option<usize>) {
+        // Remember the shuffled play order by track ID so it survives the reordering
+        let shuffled_ids: Vec<u64> = self.shuffle_order.iter()
+            .filter_map(|&index| self.tracks.get(index).map(|track| track.id))
+            .collect();
+
+        self.tracks = tracks.into();
+        self.current_position = current_position.filter(|&current| current < self.tracks.len());
+        if self.current_position.is_none() {
+            self.playback_state = PlaybackState::Stopped;
+        }
+
+        self.update_positions();
+        self.drop_orphaned_stop_marker();
+        if self.shuffle {
+            self.shuffle_order = shuffled_ids.iter()
+                .filter_map(|&id| self.position_of(id))
+                .collect();
+
+            let mut added: Vec<usize> = (0..self.tracks.len())
+                .filter(|index| !self.shuffle_order.contains(index))
+                .collect();
+            fastrand::shuffle(&mut added);
+            self.shuffle_order.extend(added);
+        } else {
+            self.shuffle_order.clear();
+        }
+    }
+
+    /// Sort the queue by `key` with a stable sort, using a secondary key to break
+    /// ties. Tracks missing the sorted field go last. The current track stays current.
+    pub fn sort_by(&mut self, key: SortKey, order: SortOrder) {
+        self.sort_by_with_stats(key, order, &StatsStore::new());
+    }
+
+    /// Like `sort_by`, taking play statistics for the statistic keys from `stats`
+    pub fn sort_by_with_stats(&mut self, key: SortKey, order: SortOrder, stats: &StatsStore) {
+        let before: Vec<AudioTrack> = self.tracks.iter().cloned().collect();
+        let before_position = self.current_position;
+        let current_id = self.get_current_track().map(|track| track.id);
+
+        let mut sorted = before.clone();
+        sorted.sort_by(|a, b| {
+            let primary = match (Self::sort_value(a, key, stats), Self::sort_value(b, key, stats)) {
+                (Some(a), Some(b)) => match order {
+                    SortOrder::Ascending => a.cmp(&b),
+                    SortOrder::Descending => b.cmp(&a),
+                },
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+
+            let secondary = key.secondary();
+            primary.then_with(|| Self::sort_value(a, secondary, stats).cmp(&Self::sort_value(b, secondary, stats)))
+        });
+
+        let after_position = current_id
+            .and_then(|id| sorted.iter().position(|track| track.id == id));
+        self.replace_raw(sorted.clone(), after_position);
+        self.journal.record(QueueOperation::Replace {
+            before,
+            before_position,
+            after: sorted,
+            after_position,
+        });
+    }
+
+    fn sort_value(track: &AudioTrack, key: SortKey, stats: &StatsStore) -> Option<SortValue> {
+        match key {
+            SortKey::Title => track.title.as_ref().map(|title| SortValue::Text(title.to_lowercase())),
+            SortKey::Artist => track.artist.as_ref().map(|artist| SortValue::Text(artist.to_lowercase())),
+            SortKey::Duration => track.playable_duration().map(SortValue::Number),
+            SortKey::Path => Some(SortValue::Text(track.path.to_string_lossy().to_lowercase())),
+            SortKey::Plays => Some(SortValue::Number(stats.get(&track.path).play_count as f64)),
+            SortKey::Skips => Some(SortValue::Number(stats.get(&track.path).skip_count as f64)),
+            SortKey::LastPlayed => stats.get(&track.path).last_played
+                .map(|at| SortValue::Number(at.timestamp_millis() as f64)),
+            SortKey::Listened => Some(SortValue::Number(stats.get(&track.path).listened)),
+            SortKey::Rating => track.rating.map(|rating| SortValue::Number(rating as f64)),
+        }
+    }
+
+    /// Revert the most recent queue change
+    pub fn undo(&mut self) -> Result<QueueOperation> {
+        let operation = self.journal.undo.pop()
+            .ok_or_else(|| anyhow!("Nothing to undo"))?;
+
+        match &operation {
+            QueueOperation::Add { position, .. } => {
+                self.check_journal_position(*position, self.tracks.len())?;
+                self.remove_raw(*position);
+            }
+            QueueOperation::Remove { position, track, was_current } => {
+                self.check_journal_position(*position, self.tracks.len() + 1)?;
+                self.insert_raw(*position, track.clone());
+                if *was_current {
+                    self.current_position = Some(*position);
+                }
+            }
+            QueueOperation::Move { from, to } => {
+                self.check_journal_position(*from.max(to), self.tracks.len())?;
+                self.move_raw(*to, *from);
+            }
+            QueueOperation::Replace { before, before_position, .. } => {
+                self.replace_raw(before.clone(), *before_position);
+            }
+            QueueOperation::AddNext { track } => {
+                self.check_journal(Self::is_journal_track(self.up_next.back(), track))?;
+                self.up_next.pop_back();
+            }
+            QueueOperation::TakeNext { position, track, previous_position } => {
+                self.check_journal(Self::is_journal_track(self.tracks.get(*position), track))?;
+                let track = self.tracks[*position].clone();
+                self.up_next.push_front(track);
+                self.remove_raw(*position);
+                self.current_position = *previous_position;
+                if self.current_position.is_none() {
+                    self.playback_state = PlaybackState::Stopped;
+                }
+            }
+            QueueOperation::Promote { positions, tracks } => {
+                let promoted = self.up_next.len().saturating_sub(tracks.len());
+                let in_lane = self.up_next.len() >= tracks.len() && self.up_next.range(promoted..)
+                    .zip(tracks)
+                    .all(|(queued, track)| Self::is_journal_track(Some(queued), track));
+                self.check_journal(in_lane)?;
+                if let Some(&last) = positions.last() {
+                    self.check_journal_position(last, self.tracks.len() + positions.len())?;
+                }
+                let tracks: Vec<AudioTrack> = self.up_next.drain(promoted..).collect();
+                for (&position, track) in positions.iter().zip(tracks) {
+                    self.insert_raw(position, track);
+                }
+            }
+        }
+
+        self.journal.redo.push(operation.clone());
+        Ok(operation)
+    }
+
+    /// Re-apply the most recently undone queue change
+    pub fn redo(&mut self) -> Result<QueueOperation> {
+        let operation = self.journal.redo.pop()
+            .ok_or_else(|| anyhow!("Nothing to redo"))?;
+
+        match &operation {
+            QueueOperation::Add { position, track } => {
+                self.check_journal_position(*position, self.tracks.len() + 1)?;
+                self.insert_raw(*position, track.clone());
+            }
+            QueueOperation::Remove { position, .. } => {
+                self.check_journal_position(*position, self.tracks.len())?;
+                self.remove_raw(*position);
+            }
+            QueueOperation::Move { from, to } => {
+                self.check_journal_position(*from.max(to), self.tracks.len())?;
+                self.move_raw(*from, *to);
+            }
+            QueueOperation::Replace { after, after_position, .. } => {
+                self.replace_raw(after.clone(), *after_position);
+            }
+            QueueOperation::AddNext { track } => {
+                self.up_next.push_back(track.clone());
+            }
+            QueueOperation::TakeNext { track, .. } => {
+                self.check_journal(Self::is_journal_track(self.up_next.front(), track))?;
+                let track = self.up_next.pop_front().expect("lane checked above");
+                self.play_next_raw(track);
+            }
+            QueueOperation::Promote { positions, .. } => {
+                if let Some(&last) = positions.last() {
+                    self.check_journal_position(last, self.tracks.len())?;
+                }
+                self.promote_raw(positions);
+            }
+        }
+
+        self.journal.undo.push(operation.clone());
+        Ok(operation)
+    }
+
+    /// A journal entry that no longer fits the queue means the journal is stale,
+    /// so drop it entirely rather than applying it to the wrong tracks
+    fn check_journal_position(&mut self, position: usize, limit: usize) -> Result<()> {
+        self.check_journal(position < limit)
+    }
+
+    /// Whether a journal entry that expects `track` finds it where it left it
+    fn is_journal_track(found: Option<&AudioTrack>, track: &AudioTrack) -> bool {
+        found.is_some_and(|found| found.id == track.id)
+    }
+
+    fn check_journal(&mut self, valid: bool) -> Result<()> {
+        if !valid {
+            self.journal.clear();
+            return Err(anyhow!("Queue changed since this operation, journal cleared"));
+        }
+        Ok(())
+    }
+
+    pub fn get_journal(&self) -> &OperationJournal {
+        &self.journal
+    }
+
+    pub fn play(&mut self) -> Result<()> {
+        if self.tracks.is_empty() && self.up_next.is_empty() {
+            return Err(anyhow!("Queue is empty"));
+        }
+
+        if self.current_position.is_none() && !self.take_up_next() {
+            self.current_position = self.play_order().first().copied();
+        }
+
+        let was_playing = self.playback_state == PlaybackState::Playing;
+        self.playback_state = PlaybackState::Playing;
+        if !was_playing {
+            self.record_history();
+        }
+        Ok(())
+    }
+
+    pub fn pause(&mut self) -> Result<()> {
+        if self.current_position.is_none() {
+            return Err(anyhow!("No track is currently selected"));
+        }
+
+        self.playback_state = PlaybackState::Paused;
+
+        // Bank the time played so far
+        let now = Utc::now();
+        if let Some(clock) = &mut self.clock {
+            if let Some(resumed_at) = clock.resumed_at.take() {
+                clock.elapsed += (now - resumed_at).num_milliseconds() as f64 / 1000.0;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<()> {
+        if self.current_position.is_none() {
+            return Err(anyhow!("No track is currently selected"));
+        }
+
+        if matches!(self.playback_state, PlaybackState::Paused) {
+            self.playback_state = PlaybackState::Playing;
+            if let Some(clock) = &mut self.clock {
+                clock.resumed_at = Some(Utc::now());
+            }
+        } else {
+            self.play()?;
+        }
+
+        Ok(())
+    }
+
+    /// Position `next_track` would move to, honoring shuffle and the repeat mode
+    pub fn next_position(&self) -> Option<usize> {
+        // A lane track is inserted right after the current track
+        if !self.up_next.is_empty() {
+            return Some(self.current_position.map_or(0, |current| current + 1));
+        }
+
+        let order = self.play_order();
+        match self.current_position {
+            Some(current) => {
+                let index = order.iter().position(|&p| p == current).unwrap_or(0);
+                match order.get(index + 1) {
+                    Some(&next) => Some(next),
+                    None if self.repeat == RepeatMode::All => order.first().copied(),
+                    None => None,
+                }
+            }
+            None => order.first().copied(),
+        }
+    }
+
+    /// Position `previous` would move to: the last track heard before the
+    /// current one, or the previous track in play order without any history
+    pub fn previous_position(&self) -> Option<usize> {
+        self.history_previous()
+            .map(|(_, position)| position)
+            .or_else(|| self.order_previous_position())
+    }
+
+    /// Most recent history entry, other than the playing track, that is still queued.
+    /// Returns the history index along with the track's queue position.
+    fn history_previous(&self) -> Option<(usize, usize)> {
+        let current_id = self.get_current_track().map(|track| track.id);
+        let mut entries = self.history.iter().enumerate().rev().peekable();
+        if entries.peek().is_some_and(|(_, entry)| Some(entry.track.id) == current_id) {
+            entries.next();
+        }
+
+        entries.find_map(|(index, entry)| {
+            self.position_of(entry.track.id).map(|position| (index, position))
+        })
+    }
+
+    /// Previous position in play order, honoring shuffle and the repeat mode
+    fn order_previous_position(&self) -> Option<usize> {
+        let order = self.play_order();
+        match self.current_position {
+            Some(current) => {
+                let index = order.iter().position(|&p| p == current).unwrap_or(0);
+                if index > 0 {
+                    Some(order[index - 1])
+                } else if self.repeat == RepeatMode::All {
+                    order.last().copied()
+                } else {
+                    None
+                }
+            }
+            None => order.first().copied(),
+        }
+    }
+
+    /// Move to the next track. In consume mode the track that was playing is removed.
+    pub fn next_track(&mut self) -> Result<()> {
+        let consumed = self.consumable_track();
+        let result = self.advance();
+        if let Some(id) = consumed {
+            self.consume_track(id);
+        }
+        result
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        if self.tracks.is_empty() && self.up_next.is_empty() {
+            return Err(anyhow!("Queue is empty"));
+        }
+
+        if self.take_up_next() {
+            self.record_history_if_playing();
+            return Ok(());
+        }
+
+        match self.next_position() {
+            Some(next) => {
+                self.current_position = Some(next);
+                self.record_history_if_playing();
+                Ok(())
+            }
+            None => Err(anyhow!("Already at last track")),
+        }
+    }
+
+    pub fn previous(&mut self) -> Result<()> {
         if self.tracks.is_empty() {
+            return Err(anyhow!("Queue is empty"));
+        }
+
+        // Going back through the history drops the entries above the target,
+        // which is then the track playing now
+        if let Some((entry, position)) = self.history_previous() {
+            self.history.truncate(entry + 1);
+            self.current_position = Some(position);
+            return Ok(());
+        }
+
+        match self.order_previous_position() {
+            Some(previous) => {
+                self.current_position = Some(previous);
+                Ok(())
+            }
+            None => Err(anyhow!("Already at first track")),
+        }
+    }
+
+    /// Advance after the current track finished playing on its own.
+    /// `RepeatMode::One` stays on the same track, other modes behave like `next_track`.
+    pub fn advance_after_finish(&mut self) -> Result<()> {
+        if self.tracks.is_empty() {
+            return Err(anyhow!("Queue is empty"));
+        }
+
+        if self.repeat == RepeatMode::One && self.get_current_track().is_some() {
+            self.record_history_if_playing();
+            return Ok(());
+        }
+
+        self.next_track()
+    }
+
+    /// ID of the track after which playback stops
+    pub fn stop_after(&self) -> Option<u64> {
+        self.stop_after
+    }
+
+    /// Stop playback once the track with `id` finishes, instead of advancing.
+    /// None removes the marker.
+    pub fn set_stop_after(&mut self, id: Option<u64>) -> Result<()> {
+        if let Some(id) = id {
+            let queued = self.tracks.iter().chain(self.up_next.iter()).any(|track| track.id == id);
+            if !queued {
+                return Err(anyhow!("No track with ID {}", id));
+            }
+        }
+        self.stop_after = id;
+        Ok(())
+    }
+
+    /// Handle the end of the current track if it carries the stop marker: clear the
+    /// marker, select the next track without starting it and return true. Returns
+    /// false, changing nothing, if playback should advance as usual.
+    pub fn stop_at_marker(&mut self) -> bool {
+        let marked = self.get_current_track()
+            .is_some_and(|track| self.stop_after == Some(track.id));
+        if !marked {
+            return false;
+        }
+
+        let consumed = self.consumable_track();
+        self.stop_after = None;
+        self.playback_state = PlaybackState::Stopped;
+        self.clock = None;
+        // At the end of the queue the last track simply stays selected
+        let _ = self.advance();
+        if let Some(id) = consumed {
+            self.consume_track(id);
+        }
+        true
+    }
+
+    pub fn is_consume_enabled(&self) -> bool {
+        self.consume
+    }
+
+    /// Enable or disable consume mode, in which a track is removed from the queue
+    /// as soon as it finishes or is skipped with `next_track` or `jump_to`
+    pub fn set_consume(&mut self, enabled: bool) {
+        self.consume = enabled;
+    }
+
+    /// ID of the current track if leaving it now should remove it. Only tracks
+    /// that were started count, so browsing a stopped queue keeps everything.
+    fn consumable_track(&self) -> Option<u64> {
+        if !self.consume || self.playback_state == PlaybackState::Stopped {
+            return None;
+        }
+        self.get_current_track().map(|track| track.id)
+    }
+
+    /// Remove a track left in consume mode. The removal is journaled, so it can be undone.
+    fn consume_track(&mut self, id: u64) {
+        let Some(position) = self.position_of(id) else {
+            return;
+        };
+
+        // Still current means there was no track to move on to
+        let was_current = self.current_position == Some(position);
+        let track = self.remove_raw(position);
+        if was_current {
+            self.current_position = None;
+            self.playback_state = PlaybackState::Stopped;
+        }
+        if self.stop_after == Some(id) {
+            self.stop_after = None;
+        }
+
+        self.journal.record(QueueOperation::Remove { position, track, was_current });
+    }
+
+    pub fn jump_to(&mut self, position: usize) -> Result<()> {
+        if position >= self.tracks.len() {
+            return Err(anyhow!("Position {} is out of bounds", position));
+        }
+
+        let consumed = self.consumable_track()
+            .filter(|_| self.current_position != Some(position));
+        self.current_position = Some(position);
+        self.record_history_if_playing();
+        if let Some(id) = consumed {
+            self.consume_track(id);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        self.journal.record(QueueOperation::Replace {
+            before: self.tracks.iter().cloned().collect(),
+            before_position: self.current_position,
+            after: Vec::new(),
+            after_position: None,
+        });
+
+        self.tracks.clear();
+        self.up_next.clear();
+        self.shuffle_order.clear();
+        self.current_position = None;
+        self.playback_state = PlaybackState::Stopped;
+        self.stop_after = None;
+        Ok(())
+    }
+
+    /// Played tracks, oldest first
+    pub fn get_history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    fn record_history_if_playing(&mut self) {
+        if self.playback_state == PlaybackState::Playing {
+            self.record_history();
+        }
+    }
+
+    fn record_history(&mut self) {
+        if let Some(track) = self.get_current_track().cloned() {
+            let now = Utc::now();
+
+            // Every recorded start begins the track from the top
+            self.clock = Some(PlaybackClock {
+                track_id: track.id,
+                elapsed: 0.0,
+                resumed_at: Some(now),
+            });
+            self.history.push(HistoryEntry {
+                track,
+                played_at: now,
+            });
+
+            if self.history.len() > MAX_HISTORY_ENTRIES {
+                let excess = self.history.len() - MAX_HISTORY_ENTRIES;
+                self.history.drain(..excess);
+            }
+        }
+    }
+
+    pub fn display_history(&self) -> String {
+        if self.history.is_empty() {
+            return "History is empty\n".to_string();
+        }
+
+        let mut output = String::new();
+        output.push_str("Playback History (most recent first):\n");
+        output.push_str("──────────────────────────────────────────────────\n");
+
+        for (index, entry) in self.history.iter().rev().enumerate() {
+            let played_at = entry.played_at.with_timezone(&Local);
+            output.push_str(&format!(
+                "  {:2}. [{}] {}\n",
+                index + 1,
+                played_at.format("%Y-%m-%d %H:%M:%S"),
+                entry.track
+            ));
+        }
+
+        output.push_str(&"─".repeat(50));
+        output.push('\n');
+
+        output
+    }
+
+    pub fn is_shuffle_enabled(&self) -> bool {
+        self.shuffle
+    }
+
+    /// Enable or disable shuffle. The curated order in `tracks` is never touched,
+    /// so disabling shuffle restores the original play order.
+    pub fn set_shuffle(&mut self, enabled: bool) {
+        if enabled == self.shuffle {
+            return;
+        }
+
+        self.shuffle = enabled;
+        if enabled {
+            self.reshuffle();
+        } else {
+            self.shuffle_order.clear();
+        }
+    }
+
+    /// Generate a new random play order, keeping the current track first
+    pub fn reshuffle(&mut self) {
+        let mut rest: Vec<usize> = (0..self.tracks.len())
+            .filter(|&index| Some(index) != self.current_position)
+            .collect();
+        fastrand::shuffle(&mut rest);
+
+        self.shuffle_order = self.current_position
+            .filter(|&current| current < self.tracks.len())
+            .into_iter()
+            .chain(rest)
+            .collect();
+    }
+
+    pub fn repeat_mode(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    pub fn set_repeat_mode(&mut self, mode: RepeatMode) {
+        self.repeat = mode;
+    }
+
+    /// Track indices in the order they will be played
+    pub fn play_order(&self) -> Vec<usize> {
+        if self.shuffle {
+            self.shuffle_order.clone()
+        } else {
+            (0..self.tracks.len()).collect()
+        }
+    }
+
+    fn is_valid_shuffle_order(&self) -> bool {
+        let mut seen = vec![false; self.tracks.len()];
+        self.shuffle_order.len() == self.tracks.len()
+            && self.shuffle_order.iter().all(|&index| {
+                index < seen.len() && !std::mem::replace(&mut seen[index], true)
+            })
+    }
+
+    /// Register a newly inserted track index in the shuffle order. The new
+    /// track lands at a random point after the current track so it still plays.
+    fn shuffle_insert(&mut self, index: usize) {
+        if !self.shuffle {
+            return;
+        }
+
+        for entry in self.shuffle_order.iter_mut() {
+            if *entry >= index {
+                *entry += 1;
+            }
+        }
+
+        let earliest = self.current_position
+            .and_then(|current| self.shuffle_order.iter().position(|&p| p == current))
+            .map(|slot| slot + 1)
+            .unwrap_or(0);
+        let slot = fastrand::usize(earliest..=self.shuffle_order.len());
+        self.shuffle_order.insert(slot, index);
+    }
+
+    fn shuffle_remove(&mut self, index: usize) {
+        self.shuffle_order.retain(|&entry| entry != index);
+        for entry in self.shuffle_order.iter_mut() {
+            if *entry > index {
+                *entry -= 1;
+            }
+        }
+    }
+
+    fn shuffle_move(&mut self, from: usize, to: usize) {
+        for entry in self.shuffle_order.iter_mut() {
+            if *entry == from {
+                *entry = to;
+            } else if from < to && *entry > from && *entry <= to {
+                *entry -= 1;
+            } else if from > to && *entry >= to && *entry < from {
+                *entry += 1;
+            }
+        }
+    }
+
+    /// Positions of the tracks whose `field` matches `query`
+    pub fn find(&self, query: &str, field: SearchField, mode: MatchMode) -> Result<Vec<usize>> {
+        let matcher: Box<dyn Fn(&str) -> bool> = match mode {
+            MatchMode::Substring => {
+                let needle = query.to_lowercase();
+                Box::new(move |value: &str| value.to_lowercase().contains(&needle))
+            }
+            MatchMode::Regex => {
+                let regex = RegexBuilder::new(query)
+                    .case_insensitive(true)
+                    .build()
+                    .context(format!("Invalid search pattern: {}", query))?;
+                Box::new(move |value: &str| regex.is_match(value))
+            }
+        };
+
+        let fields = match field {
+            SearchField::Any => vec![SearchField::Title, SearchField::Artist, SearchField::Path],
+            field => vec![field],
+        };
+
+        let matches_field = |track: &AudioTrack, field: SearchField| -> bool {
+            match field {
+                SearchField::Title => track.title.as_deref().is_some_and(&matcher),
+                SearchField::Artist => track.artist.as_deref().is_some_and(&matcher),
+                SearchField::Path | SearchField::Any => matcher(&track.path.to_string_lossy()),
+            }
+        };
+
+        Ok(self.tracks.iter()
+            .enumerate()
+            .filter(|(_, track)| fields.iter().any(|&field| matches_field(track, field)))
+            .map(|(position, _)| position)
+            .collect())
+    }
+
+    /// Move queued tracks into the "play next" lane, keeping their relative order.
+    /// The current track is left where it is.
+    pub fn promote_to_up_next(&mut self, positions: &[usize]) -> Result<()> {
+        if let Some(&position) = positions.iter().find(|&&p| p >= self.tracks.len()) {
+            return Err(anyhow!("Position {} is out of bounds", position));
+        }
+
+        let mut positions: Vec<usize> = positions.iter()
+            .copied()
+            .filter(|&position| Some(position) != self.current_position)
+            .collect();
+        positions.sort_unstable();
+        positions.dedup();
+        if positions.is_empty() {
+            return Ok(());
+        }
+
+        let tracks = self.promote_raw(&positions);
+        self.journal.record(QueueOperation::Promote { positions, tracks });
+        Ok(())
+    }
+
+    /// Move the tracks at the sorted `positions` to the end of the lane without
+    /// touching the journal. Returns the moved tracks.
+    fn promote_raw(&mut self, positions: &[usize]) -> Vec<AudioTrack> {
+        let tracks: Vec<AudioTrack> = positions.iter()
+            .map(|&position| self.tracks[position].clone())
+            .collect();
+
+        // Fill the lane first so a stop marker on a promoted track is kept, then
+        // remove from the back so earlier positions stay valid
+        self.up_next.extend(tracks.iter().cloned());
+        for &position in positions.iter().rev() {
+            self.remove_raw(position);
+        }
+        tracks
+    }
+
+    /// Seconds played of the current track at `now`. A stopped track starts over.
+    pub fn elapsed(&self, now: DateTime<Utc>) -> f64 {
+        let Some(track) = self.get_current_track() else {
+            return 0.0;
+        };
+        if self.playback_state == PlaybackState::Stopped {
+            return 0.0;
+        }
+
+        let elapsed = match &self.clock {
+            Some(clock) if clock.track_id == track.id => {
+                let running = clock.resumed_at
+                    .map(|resumed_at| (now - resumed_at).num_milliseconds() as f64 / 1000.0)
+                    .unwrap_or(0.0);
+                clock.elapsed + running.max(0.0)
+            }
+            _ => 0.0,
+        };
+
+        match track.playable_duration() {
+            Some(duration) => elapsed.min(duration),
+            None => elapsed,
+        }
+    }
+
+    /// Whether the current track has played to its end at `now`. Always false when
+    /// its playable duration is unknown.
+    pub fn track_ended(&self, now: DateTime<Utc>) -> bool {
+        self.playback_state == PlaybackState::Playing
+            && self.get_current_track()
+                .and_then(|track| track.playable_duration())
+                .is_some_and(|duration| self.elapsed(now) >= duration)
+    }
+
+    /// Queue length, time left and start estimates of upcoming tracks, assuming
+    /// playback runs continuously from `now`. Repeat modes are not taken into account.
+    pub fn timing(&self, now: DateTime<Utc>) -> QueueTiming {
+        let all_tracks = || self.tracks.iter().chain(self.up_next.iter());
+        let total = all_tracks().filter_map(|track| track.playable_duration()).sum();
+        let unknown_durations = all_tracks().filter(|track| track.playable_duration().is_none()).count();
+        let elapsed = self.elapsed(now);
+
+        // The lane plays first, then the rest of the play order after the current track
+        let order = self.play_order();
+        let after_current = match self.current_position {
+            Some(current) => order.iter()
+                .position(|&index| index == current)
+                .map_or(&order[..0], |at| &order[at + 1..]),
+            None => &order[..],
+        };
+        let upcoming_tracks = self.up_next.iter()
+            .map(|track| (None, track))
+            .chain(after_current.iter().map(|&index| (Some(index), &self.tracks[index])));
+
+        let mut remaining = 0.0;
+        let mut start = Some(now);
+        if let Some(track) = self.get_current_track() {
+            match track.playable_duration() {
+                Some(duration) => {
+                    let left = (duration - elapsed).max(0.0);
+                    remaining += left;
+                    start = Some(now + chrono::Duration::milliseconds((left * 1000.0) as i64));
+                }
+                None => start = None,
+            }
+        }
+
+        let mut upcoming = Vec::new();
+        for (position, track) in upcoming_tracks {
+            upcoming.push(TrackEta {
+                id: track.id,
+                position,
+                duration: track.playable_duration(),
+                starts_at: start,
+            });
+
+            match track.playable_duration() {
+                Some(duration) => {
+                    remaining += duration;
+                    start = start.map(|at| at + chrono::Duration::milliseconds((duration * 1000.0) as i64));
+                }
+                None => start = None,
+            }
+        }
+
+        QueueTiming {
+            total,
+            remaining,
+            elapsed,
+            unknown_durations,
+            upcoming,
+        }
+    }
+
+    /// Give every queued copy of the file at `path` these tags, including tracks in
+    /// the up-next lane. Returns how many tracks were updated.
+    pub fn set_tags(&mut self, path: &Path, tags: &TrackTags) -> usize {
+        let key = canonical_path(path);
+        let mut updated = 0;
+        for track in self.tracks.iter_mut().chain(self.up_next.iter_mut()) {
+            if canonical_path(&track.path) == key {
+                track.rating = tags.rating;
+                track.labels = tags.labels.clone();
+                updated += 1;
+            }
+        }
+        updated
+    }
+
+    /// Set the start and end offsets of the track at `position`; None plays from the
+    /// beginning or to the end of the file. Can be undone.
+    pub fn set_trim(&mut self, position: usize, start: Option<f64>, end: Option<f64>) -> Result<AudioTrack> {
+        let track = self.tracks.get(position)
+            .ok_or_else(|| anyhow!("Position {} is out of bounds", position))?;
+        validate_trim(start, end, track.duration)?;
+
+        self.update_track(position, |track| {
+            track.start = start;
+            track.end = end;
+        })
+    }
+
+    /// Set the gain offset in dB of the track at `position`. Can be undone.
+    pub fn set_gain(&mut self, position: usize, gain_db: f64) -> Result<AudioTrack> {
+        if !gain_db.is_finite() || gain_db.abs() > MAX_GAIN_DB {
+            return Err(anyhow!("Gain must be between -{0} and +{0} dB", MAX_GAIN_DB));
+        }
+        if position >= self.tracks.len() {
+            return Err(anyhow!("Position {} is out of bounds", position));
+        }
+
+        self.update_track(position, |track| track.gain_db = gain_db)
+    }
+
+    /// Change the track at a valid `position` as one undoable operation
+    fn update_track(&mut self, position: usize, change: impl FnOnce(&mut AudioTrack)) -> Result<AudioTrack> {
+        let before: Vec<AudioTrack> = self.tracks.iter().cloned().collect();
+        let mut after = before.clone();
+        change(&mut after[position]);
+        let updated = after[position].clone();
+
+        let current_position = self.current_position;
+        self.replace_raw(after.clone(), current_position);
+        self.journal.record(QueueOperation::Replace {
+            before,
+            before_position: current_position,
+            after,
+            after_position: current_position,
+        });
+
+        Ok(updated)
+    }
+
+    /// Positions of the tracks matching `filter`
+    pub fn filter_by_tags(&self, filter: &TagFilter) -> Vec<usize> {
+        self.tracks.iter()
+            .enumerate()
+            .filter(|(_, track)| filter.matches(track))
+            .map(|(position, _)| position)
+            .collect()
+    }
+
+    pub fn get_queue(&self) -> &VecDeque<AudioTrack> {
+        &self.tracks
+    }
+
+    pub fn get_current_track(&self) -> Option<&AudioTrack> {
+        self.current_position.and_then(|pos| self.tracks.get(pos))
+    }
+
+    pub fn get_status(&self) -> (PlaybackState, Option<AudioTrack>, usize) {
+        (
+            self.playback_state,
+            self.get_current_track().cloned(),
+            self.tracks.len(),
+        )
+    }
+
+    pub fn display_queue(&self) -> String {
+        if self.tracks.is_empty() && self.up_next.is_empty() {
             return "Queue is empty\n".to_string();
         }
 
         let mut output = String::new();
         output.push_str("Current Queue:\n");
+        if self.shuffle {
+            output.push_str("Shuffle: on\n");
+        }
+        if self.repeat != RepeatMode::Off {
+            output.push_str(&format!("Repeat: {:?}\n", self.repeat).to_lowercase());
+        }
+        if self.consume {
+            output.push_str("Consume: on\n");
+        }
         output.push_str("──────────────────────────────────────────────────\n");
 
+        let timing = self.timing(Utc::now());
+        let eta = |id: u64| -> String {
+            timing.upcoming.iter()
+                .find(|eta| eta.id == id)
+                .and_then(|eta| eta.starts_at)
+                .map(|at| format!(" @ {}", at.with_timezone(&Local).format("%H:%M:%S")))
+                .unwrap_or_default()
+        };
+        let stop = |id: u64| if self.stop_after == Some(id) { " ⏹ stop after" } else { "" };
+
         for (index, track) in self.tracks.iter().enumerate() {
             let current_marker = if self.current_position == Some(index) {
                 "▶ "
@@ -454,94 +2237,206 @@ impl AudioQueue {
                 "  "
             };
 
-            let position = format!("{:2}.", index + 1);
-            let title = track.title.as_deref()
-                .unwrap_or_else(|| track.path.file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("Unknown"));
-
-            let artist = track.artist.as_deref()
-                .unwrap_or("Unknown Artist");
-
-            let duration = track.duration
-                .map(|d| format!(" ({:.1}s)", d))
-                .unwrap_or_default();
-
             output.push_str(&format!(
-                "{} {} - {} - {}{}\n",
-                current_marker, position, title, artist, duration
+                "{} {:2}. {}{}{}\n",
+                current_marker, index + 1, Self::format_track_line(track), eta(track.id), stop(track.id)
             ));
         }
 
+        if !self.up_next.is_empty() {
+            output.push_str("Up Next:\n");
+            for (index, track) in self.up_next.iter().enumerate() {
+                output.push_str(&format!(
+                    "   +{}. {}{}{}\n",
+                    index + 1, Self::format_track_line(track), eta(track.id), stop(track.id)
+                ));
+            }
+        }
+
         output.push_str(&"─".repeat(50));
         output.push('\n');
+        output.push_str(&format!(
+            "Total: {}, remaining: {}",
+            format_duration(timing.total), format_duration(timing.remaining)
+        ));
+        if timing.unknown_durations > 0 {
+            output.push_str(&format!(" ({} tracks of unknown duration not counted)", timing.unknown_durations));
+        }
+        output.push('\n');
 
         output
     }
 
-    pub fn save_playlist<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+    fn format_track_line(track: &AudioTrack) -> String {
+        let title = track.title.as_deref()
+            .unwrap_or_else(|| track.path.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("Unknown"));
+
+        let artist = track.artist.as_deref()
+            .unwrap_or("Unknown Artist");
+
+        let duration = track.playable_duration()
+            .map(|d| format!(" ({:.1}s)", d))
+            .unwrap_or_else(|| " (duration unknown)".to_string());
+
+        let gain = if track.gain_db != 0.0 {
+            format!(" {:+.1} dB", track.gain_db)
+        } else {
+            String::new()
+        };
+
+        let trim = if track.is_trimmed() {
+            format!(" [{}-{}]",
+                format_duration(track.start.unwrap_or(0.0)),
+                track.end.map(format_duration).unwrap_or_default())
+        } else {
+            String::new()
+        };
+
+        let rating = track.rating
+            .map(|rating| format!(" {}", "★".repeat(rating as usize)))
+            .unwrap_or_default();
+
+        let labels = if track.labels.is_empty() {
+            String::new()
+        } else {
+            format!(" #{}", track.labels.iter().cloned().collect::<Vec<_>>().join(" #"))
+        };
+
+        format!("{} - {}{}{}{}{}{} [id {}]", title, artist, duration, trim, gain, rating, labels, track.id)
+    }
+
+    pub fn save_playlist<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        self.save_playlist_matching(path, &TagFilter::default())
+    }
+
+    /// Save the tracks matching `filter` as a playlist, in the format given by the
+    /// file extension (M3U unless it is `.pls`)
+    pub fn save_playlist_matching<P: AsRef<Path>>(&self, path: P, filter: &TagFilter) -> Result<usize> {
+        let path = path.as_ref();
+        let format = PlaylistFormat::from_extension(path).unwrap_or_default();
+        self.export_playlist(path, format, filter, false)
+    }
+
+    /// Save the tracks matching `filter` as a playlist in `format`. With `relative`,
+    /// paths are written relative to the playlist's directory, so the playlist can be
+    /// copied to another machine along with the music. Returns the number of tracks
+    /// written, which leaves out those whose path the format can't hold.
+    pub fn export_playlist<P: AsRef<Path>>(&self, path: P, format: PlaylistFormat, filter: &TagFilter, relative: bool) -> Result<usize> {
         let path = path.as_ref();
         let mut file = File::create(path)
             .context(format!("Failed to create playlist file: {}", path.display()))?;
 
-        // Write M3U header
-        writeln!(file, "#EXTM3U")?;
-
-        for track in &self.tracks {
-            // Write extended info if available
-            if let (Some(title), Some(artist), Some(duration)) =
-                (&track.title, &track.artist, track.duration) {
-                writeln!(file, "#EXTINF:{},{} - {}",
-                        duration.round() as i64, artist, title)?;
-            }
+        // Track paths are canonical, so the directory is too for them to share a prefix
+        let base_dir = if relative {
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            Some(dir.canonicalize()
+                .context(format!("Failed to resolve playlist directory: {}", dir.display()))?)
+        } else {
+            None
+        };
 
-            // Write path (relative if possible)
-            if let Some(path_str) = track.path.to_str() {
-                writeln!(file, "{}", path_str)?;
-            }
-        }
+        let tracks = self.tracks.iter().filter(|track| filter.matches(track));
+        let count = format.write(&mut file, tracks, base_dir.as_deref())?;
 
         file.flush()?;
+        Ok(count)
+    }
+
+    /// Replace the queue with the tracks of a playlist, see `read_playlist`, with
+    /// their ratings and labels taken from `tags`
+    pub fn load_playlist<P: AsRef<Path>>(&mut self, path: P, tags: &TagStore) -> Result<()> {
+        let mut tracks = Self::read_playlist(path)?;
+        for track in &mut tracks {
+            tags.apply(track);
+        }
+        self.replace_tracks(tracks);
         Ok(())
     }
 
-    pub fn load_playlist<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+    /// Read the tracks of an M3U, M3U8, PLS or XSPF playlist, detecting the format from
+    /// the extension or the content. Entries the playlist describes, e.g. with `#EXTINF`,
+    /// are read without probing their files. Missing files are skipped with a warning.
+    pub fn read_playlist<P: AsRef<Path>>(path: P) -> Result<Vec<AudioTrack>> {
         let path = path.as_ref();
-        let file = File::open(path)
+        let bytes = fs::read(path)
             .context(format!("Failed to open playlist file: {}", path.display()))?;
-
-        let reader = BufReader::new(file);
-        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
-
-        self.clear()?;
-
-        for line in reader.lines() {
-            let line = match line {
-                Ok(l) => l,
-                Err(_) => continue,
-            };
-            let line = line.trim();
-            if line.is_empty() {
+        let content = playlist::decode_text(&bytes);
+        let format = PlaylistFormat::detect(path, &content);
+        let entries = format.parse(&content)
+            .context(format!("Failed to parse playlist file: {}", path.display()))?;
+        let base_dir = path.parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+
+        // Files probed so far, so tracks of one cue sheet file are only probed once
+        let mut probed = HashMap::new();
+        let mut tracks = Vec::new();
+        for entry in entries {
+            // Resolve relative locations against the playlist's directory
+            let Some(track_path) = format.resolve(&entry.location, base_dir) else {
+                eprintln!("Warning: Not a local file: {}", entry.location);
                 continue;
-            }
-
-            // Resolve relative paths
-            let track_path = if Path::new(line).is_absolute() {
-                PathBuf::from(line)
-            } else {
-                base_dir.join(line)
             };
 
-            // Validate and add track
-            if Self::validate_audio_file(&track_path)? {
-                let track = Self::extract_metadata(&track_path)?;
-                self.add_track(track, None)?;
-            } else {
-                eprintln!("Warning: Track not found: {}", track_path.display());
+            // A missing or broken file only loses its own entry
+            match Self::track_from_entry(&entry, &track_path, &mut probed) {
+                Ok(track) => tracks.push(track),
+                Err(e) => eprintln!("Warning: {:#}", e),
             }
         }
 
-        Ok(())
+        Ok(tracks)
+    }
+
+    /// Build the track for a playlist entry whose file is at `path`. Fails if the file
+    /// is missing, not audio or unreadable. Entries with a known duration are trusted
+    /// as they are; other files are probed, with the playlist's metadata taking precedence.
+    fn track_from_entry(
+        entry: &PlaylistEntry,
+        path: &Path,
+        probed: &mut HashMap<PathBuf, Result<AudioTrack, String>>,
+    ) -> Result<AudioTrack> {
+        if !path.is_file() {
+            return Err(anyhow!("Track not found: {}", path.display()));
+        }
+
+        let mut track = if entry.duration.is_some() {
+            AudioTrack {
+                path: canonical_path(path),
+                title: entry.title.clone().or_else(|| {
+                    path.file_stem().and_then(|stem| stem.to_str()).map(String::from)
+                }),
+                duration: entry.duration,
+                ..Default::default()
+            }
+        } else {
+            let probe = probed.entry(path.to_path_buf()).or_insert_with(|| {
+                match Self::validate_audio_file(path) {
+                    Ok(true) => Self::extract_metadata(path),
+                    Ok(false) => Err(anyhow!("Not an audio file")),
+                    Err(e) => Err(e),
+                }
+                .map_err(|e| format!("Skipping unreadable track {}: {:#}", path.display(), e))
+            });
+            let mut track = probe.clone().map_err(|e| anyhow!(e))?;
+            if entry.title.is_some() {
+                track.title = entry.title.clone();
+            }
+            track
+        };
+
+        if entry.artist.is_some() {
+            track.artist = entry.artist.clone();
+        }
+        if entry.album.is_some() {
+            track.album = entry.album.clone();
+        }
+        track.start = entry.start;
+        track.end = entry.end;
+        track.gain_db = entry.gain_db.unwrap_or(0.0);
+        Ok(track)
     }
 }
 
@@ -570,6 +2465,7 @@ mod tests {
             artist: Some("Test Artist".to_string()),
             duration: Some(120.0),
             position: 0,
+            ..Default::default()
         };
 
         let track2 = AudioTrack {
@@ -578,6 +2474,7 @@ mod tests {
             artist: Some("Test Artist".to_string()),
             duration: Some(180.0),
             position: 0,
+            ..Default::default()
         };
 
         queue.add_track(track1, None).unwrap();
@@ -611,6 +2508,7 @@ mod tests {
             artist: Some("Test Artist".to_string()),
             duration: Some(200.0),
             position: 0,
+            ..Default::default()
         };
 
         original.add_track(track, None).unwrap();
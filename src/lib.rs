@@ -1,13 +1,28 @@
 pub mod audio_queue;
 pub mod audio_emitter;
 pub mod queue_processor;
+pub mod queue_collection;
+pub mod track_stats;
+pub mod track_tags;
+pub mod smart_playlist;
+pub mod queue_snapshot;
+pub mod named_store;
+pub mod playlist;
 
 // Re-exportar tipos públicos para uso externo
 pub use audio_queue::{
-    AudioQueue, AudioTrack, PlaybackState, QueueCommand
+    AddOutcome, AudioQueue, AudioTrack, DuplicatePolicy, HistoryEntry, MatchMode,
+    OperationJournal, PlaybackClock, PlaybackState, QueueCommand, QueueOperation, QueueTiming,
+    RepeatMode, SearchField, SortKey, SortOrder, TrackEta,
 };
 pub use audio_emitter::AudioEmitter;
 pub use queue_processor::QueueProcessor;
+pub use queue_collection::QueueCollection;
+pub use track_stats::{StatsFilter, StatsStore, TrackStats};
+pub use track_tags::{TagFilter, TagStore, TrackTags};
+pub use smart_playlist::{Rule, RuleMatch, SmartPlaylist, SmartPlaylistLibrary};
+pub use queue_snapshot::{QueueSnapshot, SnapshotLibrary};
+pub use playlist::{PlaylistEntry, PlaylistFormat};
 
 // Versión y metadatos del crate
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
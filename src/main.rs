@@ -1,5 +1,7 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::ops::Range;
 use std::path::PathBuf;
+use std::str::FromStr;
 use anyhow::{Result, Context};
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -10,8 +12,24 @@ use tokio::io::AsyncBufReadExt;
 mod audio_queue;
 mod audio_emitter;
 mod queue_processor;
-
-use audio_queue::{AudioQueue, AudioQueueState, QueueCommand};
+mod queue_collection;
+mod track_stats;
+mod track_tags;
+mod smart_playlist;
+mod queue_snapshot;
+mod named_store;
+mod playlist;
+
+use audio_queue::{
+    format_duration, parse_timestamp, validate_trim, AudioQueue, AudioTrack, DuplicatePolicy, MatchMode,
+    QueueCommand, RepeatMode, SearchField, SortKey, SortOrder, MAX_GAIN_DB,
+};
+use queue_collection::QueueCollection;
+use track_stats::{StatsFilter, StatsStore};
+use track_tags::{TagFilter, TagStore, MAX_RATING};
+use smart_playlist::{Rule, RuleMatch, SmartPlaylist, SmartPlaylistLibrary};
+use queue_snapshot::SnapshotLibrary;
+use playlist::PlaylistFormat;
 use audio_emitter::{AudioEmitter, EmitterCommand};
 use queue_processor::QueueProcessor;
 
@@ -20,33 +38,75 @@ use queue_processor::QueueProcessor;
 #[command(about = "A command-line audio queue manager")]
 #[command(version = "0.1.0")]
 struct Cli {
+    #[arg(long, global = true)]
+    /// Named queue to operate on (defaults to the active queue)
+    queue: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Add an audio file to the queue
+    /// Add an audio file to the queue, or every track of a cue sheet
     Add {
-        /// Path to the audio file
+        /// Path to the audio file or .cue sheet
         file: PathBuf,
         #[arg(short, long)]
         /// Position in queue (optional, adds to end by default)
         position: Option<usize>,
+        #[arg(long, conflicts_with = "position")]
+        /// Play right after the current track, before the rest of the queue
+        next: bool,
+        #[arg(long, value_parser = parse_timestamp)]
+        /// Start playing this far into the file (seconds, M:SS or H:MM:SS)
+        start: Option<f64>,
+        #[arg(long, value_parser = parse_timestamp)]
+        /// Stop playing this far into the file (seconds, M:SS or H:MM:SS)
+        end: Option<f64>,
+        #[arg(long, allow_negative_numbers = true, value_parser = parse_gain)]
+        /// Volume offset in dB applied while the track plays
+        gain: Option<f64>,
     },
     /// List all files in the queue
-    List,
-    /// Remove a file from the queue
+    List {
+        #[arg(long)]
+        /// Print the queue and its timing as JSON
+        json: bool,
+        #[arg(long, conflicts_with = "json")]
+        /// Show play statistics of each track
+        stats: bool,
+        #[arg(long, requires = "stats")]
+        /// Only list tracks played at least this many times
+        min_plays: Option<u32>,
+        #[arg(long, requires = "stats")]
+        /// Only list tracks played at most this many times
+        max_plays: Option<u32>,
+        #[arg(long, requires = "stats")]
+        /// Only list tracks skipped at least this many times
+        min_skips: Option<u32>,
+    },
+    /// Remove a file or a range of files (`3..7`, `3..=7`) from the queue
     Remove {
-        /// Position of the file in queue
-        position: usize,
+        /// Position or range of positions in queue
+        positions: Positions,
+        #[arg(long)]
+        /// Treat the position as a stable track ID
+        id: bool,
     },
-    /// Move a file to a new position in queue
+    /// Move a file or a block of files (`3..7`, `3..=7`) to a new position in queue
     Move {
-        /// Current position of the file
-        from: usize,
-        /// New position for the file
+        /// Current position or range of positions
+        from: Positions,
+        /// New position for the file, or for the first file of the block
         to: usize,
+        #[arg(long)]
+        /// Treat the current position as a stable track ID
+        id: bool,
+    },
+    /// Remove every file outside a range (`3..7`, `3..=7`)
+    Keep {
+        /// Range of positions to keep
+        range: Positions,
     },
     /// Play the audio queue
     Play,
@@ -62,16 +122,206 @@ enum Commands {
     Jump {
         /// Position to jump to
         position: usize,
+        #[arg(long)]
+        /// Treat the position as a stable track ID
+        id: bool,
     },
     /// Clear the entire queue
     Clear,
+    /// Undo the last add, remove, move or clear
+    Undo,
+    /// Redo the last undone queue change
+    Redo,
     /// Show current playback status
-    Status,
+    Status {
+        #[arg(long)]
+        /// Print the status and queue timing as JSON
+        json: bool,
+    },
+    /// Stop playback after a track finishes instead of advancing
+    StopAfter {
+        /// Position of the track in queue (defaults to the current track)
+        position: Option<usize>,
+        #[arg(long, requires = "position")]
+        /// Treat the position as a stable track ID
+        id: bool,
+        #[arg(long, conflicts_with = "position")]
+        /// Remove the stop marker
+        clear: bool,
+    },
     /// Set volume (0.0 to 1.0)
     Volume {
         /// Volume level (0.0 to 1.0)
         level: f32,
     },
+    /// Turn shuffle on or off (toggles when no mode is given)
+    Shuffle {
+        #[arg(value_enum)]
+        /// Shuffle mode
+        mode: Option<Switch>,
+    },
+    /// Turn consume mode on or off (toggles when no mode is given). Tracks are
+    /// removed from the queue once they finish or are skipped.
+    Consume {
+        #[arg(value_enum)]
+        /// Consume mode
+        mode: Option<Switch>,
+    },
+    /// Set the repeat mode
+    Repeat {
+        #[arg(value_enum)]
+        /// Repeat mode
+        mode: RepeatMode,
+    },
+    /// Show recently played tracks
+    History {
+        #[arg(long, num_args = 1..)]
+        /// Re-enqueue entries by their number in the history listing
+        enqueue: Vec<usize>,
+    },
+    /// Find tracks by title, artist or path
+    Find {
+        #[arg(default_value = "")]
+        /// Text to search for (matches everything when omitted)
+        query: String,
+        #[arg(short, long, value_enum, default_value_t = SearchField::Any)]
+        /// Field to match against
+        field: SearchField,
+        #[arg(short, long)]
+        /// Interpret the query as a regular expression
+        regex: bool,
+        #[arg(long, conflicts_with_all = ["remove", "next"])]
+        /// Jump to the first match
+        jump: bool,
+        #[arg(long, conflicts_with = "next")]
+        /// Remove all matches from the queue
+        remove: bool,
+        #[arg(long)]
+        /// Play all matches right after the current track
+        next: bool,
+        #[arg(long)]
+        /// Only match tracks rated at least this many stars
+        min_rating: Option<u8>,
+        #[arg(long = "label")]
+        /// Only match tracks carrying this label (repeatable)
+        labels: Vec<String>,
+    },
+    /// Play only a section of a queued track
+    Trim {
+        /// Position of the track in queue
+        position: usize,
+        #[arg(long, value_parser = parse_timestamp, required_unless_present_any = ["end", "clear"])]
+        /// Start playing this far into the file (seconds, M:SS or H:MM:SS)
+        start: Option<f64>,
+        #[arg(long, value_parser = parse_timestamp)]
+        /// Stop playing this far into the file (seconds, M:SS or H:MM:SS)
+        end: Option<f64>,
+        #[arg(long, conflicts_with_all = ["start", "end"])]
+        /// Play the whole file again
+        clear: bool,
+        #[arg(long)]
+        /// Treat the position as a stable track ID
+        id: bool,
+    },
+    /// Set the volume offset of a queued track, in dB (0 removes it)
+    Gain {
+        /// Position of the track in queue
+        position: usize,
+        #[arg(allow_negative_numbers = true, value_parser = parse_gain)]
+        /// Offset in dB, e.g. -6 for a track mastered too hot
+        gain_db: f64,
+        #[arg(long)]
+        /// Treat the position as a stable track ID
+        id: bool,
+    },
+    /// Rate the file of a queued track, from 1 to 5 stars (0 clears the rating)
+    Rate {
+        /// Position of the track in queue
+        position: usize,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=MAX_RATING as i64))]
+        /// Number of stars
+        stars: u8,
+        #[arg(long)]
+        /// Treat the position as a stable track ID
+        id: bool,
+    },
+    /// Add or remove labels on the file of a queued track
+    Label {
+        /// Position of the track in queue
+        position: usize,
+        #[arg(required_unless_present = "clear")]
+        /// Labels to add, such as chill, intro or explicit
+        labels: Vec<String>,
+        #[arg(long)]
+        /// Remove the given labels instead of adding them
+        remove: bool,
+        #[arg(long, conflicts_with_all = ["labels", "remove"])]
+        /// Remove all labels
+        clear: bool,
+        #[arg(long)]
+        /// Treat the position as a stable track ID
+        id: bool,
+    },
+    /// Save the queue as an M3U, PLS or XSPF playlist
+    Export {
+        /// Playlist file to write
+        file: PathBuf,
+        #[arg(long, value_enum)]
+        /// Playlist format (defaults to the one matching the file extension, else M3U)
+        format: Option<PlaylistFormat>,
+        #[arg(long)]
+        /// Only export tracks rated at least this many stars
+        min_rating: Option<u8>,
+        #[arg(long = "label")]
+        /// Only export tracks carrying this label (repeatable)
+        labels: Vec<String>,
+        #[arg(long)]
+        /// Write paths relative to the playlist's folder, so it can be copied along with the music
+        relative: bool,
+    },
+    /// Load the tracks of an M3U, PLS or XSPF playlist into the queue
+    Import {
+        /// Playlist file to read
+        file: PathBuf,
+        #[arg(long)]
+        /// Add the tracks to the end of the queue instead of replacing it
+        append: bool,
+    },
+    /// Sort the queue, keeping the current track selected
+    Sort {
+        #[arg(value_enum)]
+        /// Field to sort by
+        key: SortKey,
+        #[arg(short, long)]
+        /// Sort in descending order
+        desc: bool,
+    },
+    /// Set what happens when an already queued file is added again
+    Duplicates {
+        #[arg(value_enum)]
+        /// Duplicate policy
+        policy: DuplicatePolicy,
+        #[arg(long)]
+        /// Also treat files with identical content as duplicates
+        content: bool,
+    },
+    /// Remove duplicate files from the queue
+    Dedupe,
+    /// Manage named queues
+    Queue {
+        #[command(subcommand)]
+        action: QueueAction,
+    },
+    /// Manage rule-based smart playlists
+    Smart {
+        #[command(subcommand)]
+        action: SmartAction,
+    },
+    /// Save and restore named snapshots of the whole queue
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
     /// Start the daemon/service that manages playback
     Start,
     /// Play without blocking (for CLI usage)
@@ -80,6 +330,251 @@ enum Commands {
     Interactive,
 }
 
+#[derive(Subcommand)]
+enum QueueAction {
+    /// List all named queues
+    List,
+    /// Create a new empty queue
+    Create {
+        /// Name of the queue
+        name: String,
+    },
+    /// Make a queue the active one for later commands
+    Switch {
+        /// Name of the queue
+        name: String,
+    },
+    /// Delete a queue and its tracks
+    Delete {
+        /// Name of the queue
+        name: String,
+    },
+    /// Copy tracks from the selected queue to another queue
+    Copy {
+        /// Positions of the tracks to copy
+        #[arg(required = true)]
+        positions: Vec<usize>,
+        #[arg(long)]
+        /// Destination queue
+        to: String,
+    },
+    /// Move tracks from the selected queue to another queue
+    Move {
+        /// Positions of the tracks to move
+        #[arg(required = true)]
+        positions: Vec<usize>,
+        #[arg(long)]
+        /// Destination queue
+        to: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotAction {
+    /// Save the queue, including the current track and playback state
+    Save {
+        /// Name of the snapshot
+        name: String,
+    },
+    /// List saved snapshots
+    List,
+    /// Replace the queue with a saved snapshot
+    Restore {
+        /// Name of the snapshot
+        name: String,
+    },
+    /// Delete a saved snapshot
+    Delete {
+        /// Name of the snapshot
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SmartAction {
+    /// List saved smart playlists
+    List,
+    /// Print the definition of a smart playlist
+    Show {
+        /// Name of the smart playlist
+        name: String,
+    },
+    /// Save a smart playlist definition, replacing one with the same name
+    Save {
+        /// Name of the smart playlist
+        name: String,
+        #[command(flatten)]
+        definition: SmartDefinition,
+    },
+    /// Delete a smart playlist definition
+    Delete {
+        /// Name of the smart playlist
+        name: String,
+    },
+    /// Evaluate a smart playlist and load the matching files into the queue
+    Run {
+        /// Name of the smart playlist
+        name: String,
+        #[arg(long)]
+        /// Add to the queue instead of replacing its tracks
+        append: bool,
+    },
+}
+
+/// Rules of a smart playlist given on the command line
+#[derive(clap::Args)]
+struct SmartDefinition {
+    #[arg(long = "dir", required = true)]
+    /// Directory to search for files (repeatable)
+    directories: Vec<PathBuf>,
+    #[arg(long)]
+    /// Don't search subdirectories
+    no_recursive: bool,
+    #[arg(long)]
+    /// Artist contains this text
+    artist: Option<String>,
+    #[arg(long)]
+    /// Title contains this text
+    title: Option<String>,
+    #[arg(long)]
+    /// Minimum duration in seconds
+    min_duration: Option<f64>,
+    #[arg(long)]
+    /// Maximum duration in seconds
+    max_duration: Option<f64>,
+    #[arg(long = "ext")]
+    /// Allowed file extension (repeatable)
+    extensions: Vec<String>,
+    #[arg(long)]
+    /// Glob pattern the full path must match, such as '**/live/*.flac'
+    glob: Option<String>,
+    #[arg(long)]
+    /// Only files modified on or after this date (YYYY-MM-DD)
+    modified_after: Option<chrono::NaiveDate>,
+    #[arg(long)]
+    /// Only files modified on or before this date (YYYY-MM-DD)
+    modified_before: Option<chrono::NaiveDate>,
+    #[arg(long)]
+    /// Match files satisfying any rule instead of all of them
+    any: bool,
+    #[arg(long, value_enum)]
+    /// Sort the matching files
+    sort: Option<SortKey>,
+    #[arg(short, long, requires = "sort")]
+    /// Sort in descending order
+    desc: bool,
+    #[arg(long)]
+    /// Maximum number of tracks
+    limit: Option<usize>,
+}
+
+impl SmartDefinition {
+    fn into_playlist(self) -> SmartPlaylist {
+        let mut rules = Vec::new();
+        if let Some(artist) = self.artist {
+            rules.push(Rule::Artist(artist));
+        }
+        if let Some(title) = self.title {
+            rules.push(Rule::Title(title));
+        }
+        if self.min_duration.is_some() || self.max_duration.is_some() {
+            rules.push(Rule::Duration { min: self.min_duration, max: self.max_duration });
+        }
+        if !self.extensions.is_empty() {
+            rules.push(Rule::Extension(self.extensions));
+        }
+        if let Some(pattern) = self.glob {
+            rules.push(Rule::PathGlob(pattern));
+        }
+        if self.modified_after.is_some() || self.modified_before.is_some() {
+            rules.push(Rule::Modified { after: self.modified_after, before: self.modified_before });
+        }
+
+        SmartPlaylist {
+            recursive: !self.no_recursive,
+            rules,
+            match_mode: if self.any { RuleMatch::Any } else { RuleMatch::All },
+            sort: self.sort,
+            order: if self.desc { SortOrder::Descending } else { SortOrder::Ascending },
+            limit: self.limit,
+            ..SmartPlaylist::new(self.directories)
+        }
+    }
+}
+
+/// How `label` changes the labels of a file
+enum LabelChange {
+    Add(Vec<String>),
+    Remove(Vec<String>),
+    Clear,
+}
+
+/// What to do with the tracks matched by `find`
+enum FindAction {
+    List,
+    Jump,
+    Remove,
+    PlayNext,
+}
+
+/// Queue positions given as `N`, `A..B` (end excluded) or `A..=B`
+#[derive(Clone, Copy, Debug)]
+struct Positions {
+    start: usize,
+    end: usize,
+}
+
+impl Positions {
+    /// The position, if a single one was given
+    fn single(self) -> Option<usize> {
+        (self.end == self.start + 1).then_some(self.start)
+    }
+
+    fn range(self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl FromStr for Positions {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parse = |value: &str| value.trim().parse::<usize>()
+            .map_err(|_| format!("invalid position '{}'", value));
+        let after = |value: &str| parse(value)?.checked_add(1)
+            .ok_or_else(|| format!("position '{}' is too large", value));
+
+        let (start, end) = if let Some((start, end)) = s.split_once("..=") {
+            (parse(start)?, after(end)?)
+        } else if let Some((start, end)) = s.split_once("..") {
+            (parse(start)?, parse(end)?)
+        } else {
+            (parse(s)?, after(s)?)
+        };
+
+        if start >= end {
+            return Err(format!("range '{}' is empty", s));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+/// Parse a gain offset in dB within the allowed range
+fn parse_gain(s: &str) -> std::result::Result<f64, String> {
+    let gain_db: f64 = s.trim().trim_end_matches("dB").trim().parse()
+        .map_err(|_| format!("invalid gain '{}'", s))?;
+    if !gain_db.is_finite() || gain_db.abs() > MAX_GAIN_DB {
+        return Err(format!("gain must be between -{0} and +{0} dB", MAX_GAIN_DB));
+    }
+    Ok(gain_db)
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Switch {
+    On,
+    Off,
+}
+
 struct AudioQueueManager {
     queue: Arc<Mutex<AudioQueue>>,
     emitter: Arc<Mutex<AudioEmitter>>,
@@ -88,6 +583,8 @@ struct AudioQueueManager {
     emitter_sender: mpsc::Sender<EmitterCommand>,
     _processor_handle: tokio::task::JoinHandle<()>, // Keep processor alive
     state_file: PathBuf, // Persistent state file
+    collection: Mutex<QueueCollection>, // All named queues, persisted together
+    queue_name: String, // Name of the queue loaded into `queue`
 }
 
 impl AudioQueueManager {
@@ -97,62 +594,68 @@ impl AudioQueueManager {
         path
     }
 
+    fn get_tags_file_path() -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push("audioqueue_tags.json");
+        path
+    }
+
+    fn get_snapshot_dir() -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push("audioqueue_snapshots");
+        path
+    }
+
+    fn get_smart_playlist_dir() -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push("audioqueue_smart");
+        path
+    }
+
+    fn get_stats_file_path() -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push("audioqueue_stats.json");
+        path
+    }
+
     async fn save_state(&self) -> Result<()> {
         let queue = self.queue.lock().await;
-        let state = AudioQueueState {
-            tracks: queue.get_queue().iter().cloned().collect(),
-            current_position: if queue.get_current_track().is_some() {
-                Some(queue.get_queue().len() - 1)
-            } else {
-                None
-            },
-            playback_state: queue.get_status().0,
-        };
-        drop(queue);
+        let mut collection = self.collection.lock().await;
 
-        let content = serde_json::to_string_pretty(&state)
-            .context("Failed to serialize queue state")?;
-
-        std::fs::write(&self.state_file, content)
-            .context("Failed to write state file")?;
+        // The selected queue may have been deleted by this command
+        if collection.contains(&self.queue_name) {
+            collection.store(&self.queue_name, &queue);
+        }
 
-        Ok(())
+        collection.save(&self.state_file)
+            .context("Failed to write state file")
     }
 
-    async fn new() -> Result<Self> {
+    async fn new(queue_name: Option<String>) -> Result<Self> {
         let state_file = Self::get_state_file_path();
 
-        // Try to load existing queue state
-        let queue = if state_file.exists() {
-            match std::fs::read_to_string(&state_file) {
-                Ok(content) => {
-                    match serde_json::from_str::<AudioQueueState>(&content) {
-                        Ok(state) => {
-                            let mut queue = AudioQueue::new();
-                            for track in state.tracks {
-                                queue.add_track(track, None).unwrap_or_default();
-                            }
-                            if let Some(pos) = state.current_position {
-                                queue.jump_to(pos).unwrap_or_default();
-                            }
-                            queue.playback_state = state.playback_state;
-                            Arc::new(Mutex::new(queue))
-                        }
-                        Err(_) => {
-                            eprintln!("Warning: Invalid state file, creating new queue");
-                            Arc::new(Mutex::new(AudioQueue::new()))
-                        }
-                    }
-                }
-                Err(_) => {
-                    eprintln!("Warning: Could not read state file, creating new queue");
-                    Arc::new(Mutex::new(AudioQueue::new()))
-                }
+        // Try to load existing queue state. An unreadable file is kept as a backup,
+        // since the next save would otherwise overwrite every named queue in it.
+        let collection = match QueueCollection::load(&state_file) {
+            Ok(collection) => collection,
+            Err(e) => {
+                let backup = state_file.with_extension("json.bak");
+                std::fs::rename(&state_file, &backup)
+                    .context(format!("Invalid state file {} could not be backed up", state_file.display()))?;
+                eprintln!("Warning: Invalid state file ({:#}), moved it to {} and created a new queue",
+                    e, backup.display());
+                QueueCollection::new()
             }
-        } else {
-            Arc::new(Mutex::new(AudioQueue::new()))
         };
 
+        let queue_name = queue_name.unwrap_or_else(|| collection.active.clone());
+        if !collection.contains(&queue_name) {
+            return Err(anyhow::anyhow!(
+                "Queue '{}' does not exist, create it with `audioqueue queue create {}`", queue_name, queue_name
+            ));
+        }
+        let queue = Arc::new(Mutex::new(collection.queue(&queue_name)?));
+
         let emitter = Arc::new(Mutex::new(AudioEmitter::new()?));
 
         // Create channels for queue commands
@@ -172,7 +675,8 @@ impl AudioQueueManager {
         let queue_clone = queue.clone();
         let emitter_sender_clone = emitter_sender.clone();
         let processor_handle = tokio::spawn(async move {
-            let mut processor = QueueProcessor::new(queue_clone, emitter_sender_clone, queue_rx);
+            let mut processor = QueueProcessor::new(queue_clone, emitter_sender_clone, queue_rx)
+                .with_stats_file(Self::get_stats_file_path());
             if let Err(e) = processor.run().await {
                 eprintln!("Queue processor error: {}", e);
             }
@@ -188,10 +692,19 @@ impl AudioQueueManager {
             emitter_sender,
             _processor_handle: processor_handle,
             state_file,
+            collection: Mutex::new(collection),
+            queue_name,
         })
     }
 
-    async fn handle_add(&self, file: PathBuf, position: Option<usize>) -> Result<()> {
+    async fn handle_add(&self, file: PathBuf, position: Option<usize>, next: bool, start: Option<f64>, end: Option<f64>, gain_db: f64) -> Result<()> {
+        if PlaylistFormat::from_extension(&file) == Some(PlaylistFormat::Cue) {
+            if start.is_some() || end.is_some() {
+                return Err(anyhow::anyhow!("--start and --end cannot be used with a cue sheet"));
+            }
+            return self.handle_add_cue(file, position, next, gain_db).await;
+        }
+
         // Convert to absolute path before validation
         let absolute_file = if file.is_absolute() {
             file
@@ -209,10 +722,31 @@ impl AudioQueueManager {
         }
 
         // Extract metadata (will also convert to absolute)
-        let track = AudioQueue::extract_metadata(&absolute_file)?;
+        let mut track = AudioQueue::extract_metadata(&absolute_file)?;
+        TagStore::load(Self::get_tags_file_path())?.apply(&mut track);
+        validate_trim(start, end, track.duration)?;
+        track.start = start;
+        track.end = end;
+        track.gain_db = gain_db;
+
+        let (policy, duplicate) = {
+            let queue = self.queue.lock().await;
+            let policy = queue.duplicate_policy();
+            let duplicate = if next || policy == DuplicatePolicy::Allow {
+                None
+            } else {
+                queue.find_duplicate(&track)
+            };
+            (policy, duplicate)
+        };
 
         // Add to queue
-        self.queue_sender.send(QueueCommand::Add(track, position)).await?;
+        let command = if next {
+            QueueCommand::AddNext(track)
+        } else {
+            QueueCommand::Add(track, position)
+        };
+        self.queue_sender.send(command).await?;
 
         // Wait for the processor to handle the command
         tokio::time::sleep(Duration::from_millis(50)).await;
@@ -220,7 +754,51 @@ impl AudioQueueManager {
         // Save state after modification
         self.save_state().await?;
 
-        println!("Added {} to queue", absolute_file.display());
+        match duplicate {
+            Some(existing) if policy == DuplicatePolicy::Skip => {
+                println!("{} is already queued at position {}, skipped", absolute_file.display(), existing);
+            }
+            Some(existing) => {
+                println!("{} is already queued, moved it from position {}", absolute_file.display(), existing);
+            }
+            None => println!("Added {} to queue", absolute_file.display()),
+        }
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    /// Add one queue entry per track of a cue sheet, each playing its region of the file
+    async fn handle_add_cue(&self, file: PathBuf, position: Option<usize>, next: bool, gain_db: f64) -> Result<()> {
+        let tags = TagStore::load(Self::get_tags_file_path())?;
+        let tracks = AudioQueue::read_playlist(&file)?;
+        if tracks.is_empty() {
+            return Err(anyhow::anyhow!("No playable tracks in {}", file.display()));
+        }
+
+        let total = tracks.len();
+        for (index, mut track) in tracks.into_iter().enumerate() {
+            tags.apply(&mut track);
+            validate_trim(track.start, track.end, track.duration)?;
+            if gain_db != 0.0 {
+                track.gain_db = gain_db;
+            }
+            let command = if next {
+                QueueCommand::AddNext(track)
+            } else {
+                QueueCommand::Add(track, position.map(|position| position + index))
+            };
+            self.queue_sender.send(command).await?;
+        }
+
+        // Wait for the processor to handle the commands
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        println!("Added {} tracks from {} to queue", total, file.display());
 
         // Show updated queue
         self.handle_list().await?;
@@ -233,8 +811,82 @@ impl AudioQueueManager {
         Ok(())
     }
 
-    async fn handle_remove(&self, position: usize) -> Result<()> {
-        self.queue_sender.send(QueueCommand::Remove(position)).await?;
+    async fn handle_list_stats(&self, filter: StatsFilter) -> Result<()> {
+        let stats = StatsStore::load(Self::get_stats_file_path())?;
+        let queue = self.queue.lock().await;
+        let positions = stats.matching_positions(queue.get_queue(), &filter);
+
+        if positions.is_empty() {
+            println!("No tracks match the statistics filter");
+            return Ok(());
+        }
+
+        println!("{:>4}  {:>5}  {:>5}  {:>9}  {:<19}  Track", "Pos", "Plays", "Skips", "Listened", "Last played");
+        for position in positions {
+            let track = &queue.get_queue()[position];
+            let track_stats = stats.get(&track.path);
+            let last_played = track_stats.last_played
+                .map(|at| at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| "never".to_string());
+            let marker = if queue.current_position == Some(position) { "▶" } else { " " };
+
+            println!("{}{:>3}  {:>5}  {:>5}  {:>9}  {:<19}  {}",
+                marker, position, track_stats.play_count, track_stats.skip_count,
+                format_duration(track_stats.listened), last_played, track);
+        }
+        Ok(())
+    }
+
+    async fn handle_list_json(&self) -> Result<()> {
+        let queue = self.queue.lock().await;
+        let output = serde_json::json!({
+            "queue": self.queue_name,
+            "playback_state": queue.playback_state,
+            "current_position": queue.current_position,
+            "tracks": queue.get_queue(),
+            "up_next": queue.get_up_next(),
+            "timing": queue.timing(chrono::Utc::now()),
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        Ok(())
+    }
+
+    async fn handle_remove(&self, positions: Positions, by_id: bool) -> Result<()> {
+        let command = match (positions.single(), by_id) {
+            (Some(id), true) => QueueCommand::RemoveById(id as u64),
+            (None, true) => return Err(anyhow::anyhow!("Ranges cannot be used with --id")),
+            (Some(position), false) => QueueCommand::Remove(position),
+            (None, false) => QueueCommand::RemoveRange(positions.range()),
+        };
+        self.queue_sender.send(command).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        if by_id {
+            println!("Removed track with ID {}", positions.start);
+        } else if let Some(position) = positions.single() {
+            println!("Removed item at position {}", position);
+        } else {
+            println!("Removed items at positions {}..{}", positions.start, positions.end);
+        }
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    async fn handle_move(&self, from: Positions, to: usize, by_id: bool) -> Result<()> {
+        let command = match (from.single(), by_id) {
+            (Some(id), true) => QueueCommand::MoveById(id as u64, to),
+            (None, true) => return Err(anyhow::anyhow!("Ranges cannot be used with --id")),
+            (Some(position), false) => QueueCommand::Move(position, to),
+            (None, false) => QueueCommand::MoveBlock(from.range(), to),
+        };
+        self.queue_sender.send(command).await?;
 
         // Wait for the processor to handle the command
         tokio::time::sleep(Duration::from_millis(50)).await;
@@ -242,15 +894,21 @@ impl AudioQueueManager {
         // Save state after modification
         self.save_state().await?;
 
-        println!("Removed item at position {}", position);
+        if by_id {
+            println!("Moved track with ID {} to position {}", from.start, to);
+        } else if let Some(position) = from.single() {
+            println!("Moved item from position {} to {}", position, to);
+        } else {
+            println!("Moved items {}..{} to position {}", from.start, from.end, to);
+        }
 
         // Show updated queue
         self.handle_list().await?;
         Ok(())
     }
 
-    async fn handle_move(&self, from: usize, to: usize) -> Result<()> {
-        self.queue_sender.send(QueueCommand::Move(from, to)).await?;
+    async fn handle_keep(&self, range: Positions) -> Result<()> {
+        self.queue_sender.send(QueueCommand::KeepRange(range.range())).await?;
 
         // Wait for the processor to handle the command
         tokio::time::sleep(Duration::from_millis(50)).await;
@@ -258,7 +916,7 @@ impl AudioQueueManager {
         // Save state after modification
         self.save_state().await?;
 
-        println!("Moved item from position {} to {}", from, to);
+        println!("Kept items at positions {}..{}", range.start, range.end);
 
         // Show updated queue
         self.handle_list().await?;
@@ -273,6 +931,7 @@ impl AudioQueueManager {
         // Get the current track from the queue
         let queue = self.queue.lock().await;
         if let Some(track) = queue.get_current_track() {
+            let track = track.clone();
             let file_path = track.path.to_string_lossy().to_string();
             drop(queue);
 
@@ -284,7 +943,7 @@ impl AudioQueueManager {
                 eprintln!("Warning: Could not stop previous playback: {}", e);
             }
 
-            if let Err(e) = emitter.load_file(&file_path) {
+            if let Err(e) = emitter.load_track(&track) {
                 eprintln!("Error loading file {}: {}", file_path, e);
                 return Err(e);
             }
@@ -399,7 +1058,7 @@ impl AudioQueueManager {
                             let parts: Vec<&str> = cmd.split_whitespace().collect();
                             if parts.len() == 2 {
                                 let path = PathBuf::from(parts[1]);
-                                if let Err(e) = self.handle_add(path, None).await {
+                                if let Err(e) = self.handle_add(path, None, false, None, None, 0.0).await {
                                     eprintln!("Error: {}", e);
                                 }
                             } else {
@@ -461,6 +1120,14 @@ impl AudioQueueManager {
     }
 
     async fn handle_next(&self) -> Result<()> {
+        {
+            let queue = self.queue.lock().await;
+            if !queue.get_queue().is_empty() && queue.next_position().is_none() {
+                println!("Already at last track (repeat is {:?})", queue.repeat_mode());
+                return Ok(());
+            }
+        }
+
         // Stop current playback first
         if let Err(e) = self.emitter.lock().await.stop() {
             eprintln!("Warning: Could not stop playback: {}", e);
@@ -475,12 +1142,13 @@ impl AudioQueueManager {
         // Try to play the next track
         let queue = self.queue.lock().await;
         if let Some(track) = queue.get_current_track() {
+            let track = track.clone();
             let file_path = track.path.to_string_lossy().to_string();
             drop(queue);
 
             let mut emitter = self.emitter.lock().await;
 
-            if let Err(e) = emitter.load_file(&file_path) {
+            if let Err(e) = emitter.load_track(&track) {
                 eprintln!("Error loading next file {}: {}", file_path, e);
             } else if let Err(e) = emitter.play() {
                 eprintln!("Error playing next file {}: {}", file_path, e);
@@ -498,6 +1166,14 @@ impl AudioQueueManager {
     }
 
     async fn handle_previous(&self) -> Result<()> {
+        {
+            let queue = self.queue.lock().await;
+            if !queue.get_queue().is_empty() && queue.previous_position().is_none() {
+                println!("Already at first track (repeat is {:?})", queue.repeat_mode());
+                return Ok(());
+            }
+        }
+
         self.queue_sender.send(QueueCommand::Previous).await?;
 
         // Wait for the processor to handle the command
@@ -511,8 +1187,13 @@ impl AudioQueueManager {
         Ok(())
     }
 
-    async fn handle_jump(&self, position: usize) -> Result<()> {
-        self.queue_sender.send(QueueCommand::Jump(position)).await?;
+    async fn handle_jump(&self, position: usize, by_id: bool) -> Result<()> {
+        let command = if by_id {
+            QueueCommand::JumpById(position as u64)
+        } else {
+            QueueCommand::Jump(position)
+        };
+        self.queue_sender.send(command).await?;
 
         // Wait for the processor to handle the command
         tokio::time::sleep(Duration::from_millis(50)).await;
@@ -520,7 +1201,11 @@ impl AudioQueueManager {
         // Save state after modification
         self.save_state().await?;
 
-        println!("Jumped to position {}", position);
+        if by_id {
+            println!("Jumped to track with ID {}", position);
+        } else {
+            println!("Jumped to position {}", position);
+        }
         self.handle_status().await?;
         Ok(())
     }
@@ -543,6 +1228,44 @@ impl AudioQueueManager {
         Ok(())
     }
 
+    async fn handle_undo(&self) -> Result<()> {
+        if !self.queue.lock().await.get_journal().can_undo() {
+            println!("Nothing to undo");
+            return Ok(());
+        }
+
+        self.queue_sender.send(QueueCommand::Undo).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    async fn handle_redo(&self) -> Result<()> {
+        if !self.queue.lock().await.get_journal().can_redo() {
+            println!("Nothing to redo");
+            return Ok(());
+        }
+
+        self.queue_sender.send(QueueCommand::Redo).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
     async fn handle_status(&self) -> Result<()> {
         self.queue_sender.send(QueueCommand::GetStatus).await?;
 
@@ -550,45 +1273,672 @@ impl AudioQueueManager {
         tokio::time::sleep(Duration::from_millis(100)).await;
 
         // Also get direct status from queue for immediate response
-        let (state, current_track, queue_size) = {
+        let (state, current_track, queue_size, shuffle, repeat, consume, timing, stop_after) = {
             let queue = self.queue.lock().await;
-            queue.get_status()
+            let (state, current_track, queue_size) = queue.get_status();
+            let timing = queue.timing(chrono::Utc::now());
+            let stop_after = queue.stop_after()
+                .map(|id| (id, queue.position_of(id)));
+            (state, current_track, queue_size, queue.is_shuffle_enabled(), queue.repeat_mode(),
+                queue.is_consume_enabled(), timing, stop_after)
         };
 
         println!("=== Queue Status ===");
         println!("State: {:?}", state);
         println!("Queue size: {}", queue_size);
+        println!("Shuffle: {}", if shuffle { "on" } else { "off" });
+        println!("Repeat: {:?}", repeat);
+        println!("Consume: {}", if consume { "on" } else { "off" });
+        match stop_after {
+            Some((id, Some(position))) => println!("Stop after: position {} (ID {})", position, id),
+            Some((id, None)) => println!("Stop after: up-next track (ID {})", id),
+            None => {}
+        }
 
         if let Some(track) = current_track {
             println!("Current track: {} - {} ({:.1}s)",
                 track.title.as_deref().unwrap_or("Unknown"),
                 track.artist.as_deref().unwrap_or("Unknown Artist"),
-                track.duration.unwrap_or(0.0));
+                track.playable_duration().unwrap_or(0.0));
             println!("File: {}", track.path.display());
+            if track.gain_db != 0.0 {
+                println!("Gain: {:+.1} dB", track.gain_db);
+            }
+            match track.playable_duration() {
+                Some(duration) => println!("Elapsed: {} / {}",
+                    format_duration(timing.elapsed), format_duration(duration)),
+                None => println!("Elapsed: {} / unknown", format_duration(timing.elapsed)),
+            }
         } else {
             println!("No current track");
         }
+        println!("Queue length: {}", format_duration(timing.total));
+        println!("Remaining: {}", format_duration(timing.remaining));
+        if timing.unknown_durations > 0 {
+            println!("Tracks of unknown duration (not counted): {}", timing.unknown_durations);
+        }
+        if let Some(finish) = timing.upcoming.last()
+            .and_then(|last| last.starts_at.zip(last.duration))
+            .map(|(start, duration)| start + chrono::Duration::milliseconds((duration * 1000.0) as i64))
+        {
+            println!("Queue ends at: {}", finish.with_timezone(&chrono::Local).format("%H:%M:%S"));
+        }
         println!("======================");
 
         Ok(())
     }
 
-    async fn handle_volume(&self, level: f32) -> Result<()> {
-        let clamped_level = level.clamp(0.0, 1.0);
+    async fn handle_status_json(&self) -> Result<()> {
+        let queue = self.queue.lock().await;
+        let (state, current_track, queue_size) = queue.get_status();
+        let output = serde_json::json!({
+            "queue": self.queue_name,
+            "state": state,
+            "current_track": current_track,
+            "queue_size": queue_size,
+            "shuffle": queue.is_shuffle_enabled(),
+            "repeat": queue.repeat_mode(),
+            "stop_after": queue.stop_after(),
+            "consume": queue.is_consume_enabled(),
+            "timing": queue.timing(chrono::Utc::now()),
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        Ok(())
+    }
 
-        // Set volume on emitter directly
-        if let Err(e) = self.emitter.lock().await.set_volume(clamped_level) {
-            eprintln!("Error setting volume: {}", e);
+    async fn handle_stop_after(&self, position: Option<usize>, by_id: bool, clear: bool) -> Result<()> {
+        let target = if clear {
+            None
         } else {
-            println!("Volume set to {:.2}", clamped_level);
-        }
+            let queue = self.queue.lock().await;
+            let track = match position {
+                Some(id) if by_id => queue.get_track_by_id(id as u64)
+                    .ok_or_else(|| anyhow::anyhow!("No track with ID {}", id))?,
+                Some(position) => queue.get_queue().get(position)
+                    .ok_or_else(|| anyhow::anyhow!("No track at position {}", position))?,
+                None => queue.get_current_track()
+                    .ok_or_else(|| anyhow::anyhow!("No track is playing"))?,
+            };
+            Some((track.id, track.position))
+        };
+
+        self.queue_sender.send(QueueCommand::SetStopAfter(target.map(|(id, _)| id))).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
 
         // Save state after modification
         self.save_state().await?;
 
+        match target {
+            Some((_, position)) => println!("Playback will stop after the track at position {}", position),
+            None => println!("Stop marker cleared"),
+        }
         Ok(())
     }
 
+    async fn handle_volume(&self, level: f32) -> Result<()> {
+        let clamped_level = level.clamp(0.0, 1.0);
+
+        // Set volume on emitter directly
+        if let Err(e) = self.emitter.lock().await.set_volume(clamped_level) {
+            eprintln!("Error setting volume: {}", e);
+        } else {
+            println!("Volume set to {:.2}", clamped_level);
+        }
+
+        // Save state after modification
+        self.save_state().await?;
+
+        Ok(())
+    }
+
+    async fn handle_shuffle(&self, mode: Option<Switch>) -> Result<()> {
+        let enabled = match mode {
+            Some(Switch::On) => true,
+            Some(Switch::Off) => false,
+            None => !self.queue.lock().await.is_shuffle_enabled(),
+        };
+
+        self.queue_sender.send(QueueCommand::SetShuffle(enabled)).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        println!("Shuffle {}", if enabled { "enabled" } else { "disabled" });
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    async fn handle_consume(&self, mode: Option<Switch>) -> Result<()> {
+        let enabled = match mode {
+            Some(Switch::On) => true,
+            Some(Switch::Off) => false,
+            None => !self.queue.lock().await.is_consume_enabled(),
+        };
+
+        self.queue_sender.send(QueueCommand::SetConsume(enabled)).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        println!("Consume mode {}", if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    async fn handle_repeat(&self, mode: RepeatMode) -> Result<()> {
+        self.queue_sender.send(QueueCommand::SetRepeat(mode)).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        println!("Repeat mode set to {:?}", mode);
+        Ok(())
+    }
+
+    async fn handle_history(&self, enqueue: Vec<usize>) -> Result<()> {
+        if enqueue.is_empty() {
+            let queue = self.queue.lock().await;
+            println!("{}", queue.display_history());
+            return Ok(());
+        }
+
+        // History listing is numbered from the most recent entry, starting at 1
+        let tracks = {
+            let queue = self.queue.lock().await;
+            let history = queue.get_history();
+            enqueue.iter()
+                .map(|&number| {
+                    number.checked_sub(1)
+                        .filter(|&index| index < history.len())
+                        .map(|index| history[history.len() - 1 - index].track.clone())
+                        .ok_or_else(|| anyhow::anyhow!("No history entry {}", number))
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        for track in tracks {
+            println!("Re-enqueued {}", track);
+            self.queue_sender.send(QueueCommand::Add(track, None)).await?;
+        }
+
+        // Wait for the processor to handle the commands
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    async fn handle_find(&self, query: String, field: SearchField, regex: bool, tags: TagFilter, action: FindAction) -> Result<()> {
+        let mode = if regex { MatchMode::Regex } else { MatchMode::Substring };
+        let matches = {
+            let queue = self.queue.lock().await;
+            let tagged = queue.filter_by_tags(&tags);
+            let matches: Vec<usize> = queue.find(&query, field, mode)?
+                .into_iter()
+                .filter(|position| tagged.contains(position))
+                .collect();
+            for &position in &matches {
+                println!("  pos {}: {}", position, queue.get_queue()[position]);
+            }
+            matches
+        };
+
+        if matches.is_empty() {
+            println!("No tracks match '{}'", query);
+            return Ok(());
+        }
+        println!("{} matching tracks", matches.len());
+
+        match action {
+            FindAction::List => return Ok(()),
+            FindAction::Jump => {
+                return self.handle_jump(matches[0], false).await;
+            }
+            FindAction::Remove => {
+                let count = matches.len();
+                self.queue_sender.send(QueueCommand::RemovePositions(matches)).await?;
+                println!("Removed {} tracks", count);
+            }
+            FindAction::PlayNext => {
+                self.queue_sender.send(QueueCommand::PlayNext(matches)).await?;
+            }
+        }
+
+        // Wait for the processor to handle the commands
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    async fn handle_sort(&self, key: SortKey, desc: bool) -> Result<()> {
+        let order = if desc { SortOrder::Descending } else { SortOrder::Ascending };
+        self.queue_sender.send(QueueCommand::Sort(key, order)).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        println!("Sorted queue by {:?} ({:?})", key, order);
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    /// Path of the queued track at `position`, or with that ID
+    async fn track_path(&self, position: usize, by_id: bool) -> Result<PathBuf> {
+        let queue = self.queue.lock().await;
+        let track = if by_id {
+            queue.get_track_by_id(position as u64)
+        } else {
+            queue.get_queue().get(position)
+        };
+
+        track.map(|track| track.path.clone())
+            .ok_or_else(|| anyhow::anyhow!("No track at {} {}", if by_id { "ID" } else { "position" }, position))
+    }
+
+    async fn handle_trim(&self, position: usize, by_id: bool, start: Option<f64>, end: Option<f64>, clear: bool) -> Result<()> {
+        let (position, start, end) = {
+            let queue = self.queue.lock().await;
+            let position = if by_id {
+                queue.position_of(position as u64)
+                    .ok_or_else(|| anyhow::anyhow!("No track with ID {}", position))?
+            } else {
+                position
+            };
+            let track = queue.get_queue().get(position)
+                .ok_or_else(|| anyhow::anyhow!("No track at position {}", position))?;
+
+            // Offsets that aren't given keep their current value
+            let (start, end) = if clear {
+                (None, None)
+            } else {
+                (start.or(track.start), end.or(track.end))
+            };
+            validate_trim(start, end, track.duration)?;
+            (position, start, end)
+        };
+
+        self.queue_sender.send(QueueCommand::SetTrim(position, start, end)).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        match (start, end) {
+            (None, None) => println!("Track at position {} plays in full", position),
+            (start, end) => println!("Track at position {} plays from {} to {}",
+                position,
+                format_duration(start.unwrap_or(0.0)),
+                end.map(format_duration).unwrap_or_else(|| "the end".to_string())),
+        }
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    async fn handle_gain(&self, position: usize, by_id: bool, gain_db: f64) -> Result<()> {
+        let position = {
+            let queue = self.queue.lock().await;
+            let position = if by_id {
+                queue.position_of(position as u64)
+                    .ok_or_else(|| anyhow::anyhow!("No track with ID {}", position))?
+            } else {
+                position
+            };
+            if position >= queue.get_queue().len() {
+                return Err(anyhow::anyhow!("No track at position {}", position));
+            }
+            position
+        };
+
+        self.queue_sender.send(QueueCommand::SetGain(position, gain_db)).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        println!("Track at position {} plays at {:+.1} dB", position, gain_db);
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    async fn handle_rate(&self, position: usize, by_id: bool, stars: u8) -> Result<()> {
+        let path = self.track_path(position, by_id).await?;
+        let tags_file = Self::get_tags_file_path();
+        let mut store = TagStore::load(&tags_file)?;
+        let tags = store.set_rating(&path, (stars > 0).then_some(stars))?;
+        store.save(&tags_file)?;
+
+        self.queue_sender.send(QueueCommand::SetTags(path.clone(), tags.clone())).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Copies of the file in the other queues get the new tags too
+        self.collection.lock().await.set_tags(&path, &tags);
+
+        // Save state after modification
+        self.save_state().await?;
+
+        if stars == 0 {
+            println!("Cleared rating of {}", path.display());
+        } else {
+            println!("Rated {} {} stars", path.display(), stars);
+        }
+        Ok(())
+    }
+
+    async fn handle_label(&self, position: usize, by_id: bool, change: LabelChange) -> Result<()> {
+        let path = self.track_path(position, by_id).await?;
+        let tags_file = Self::get_tags_file_path();
+        let mut store = TagStore::load(&tags_file)?;
+        let tags = match change {
+            LabelChange::Add(labels) => store.add_labels(&path, &labels)?,
+            LabelChange::Remove(labels) => store.remove_labels(&path, &labels)?,
+            LabelChange::Clear => store.clear_labels(&path)?,
+        };
+        store.save(&tags_file)?;
+
+        let labels = tags.labels.iter().cloned().collect::<Vec<_>>().join(", ");
+        self.queue_sender.send(QueueCommand::SetTags(path.clone(), tags.clone())).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Copies of the file in the other queues get the new tags too
+        self.collection.lock().await.set_tags(&path, &tags);
+
+        // Save state after modification
+        self.save_state().await?;
+
+        if labels.is_empty() {
+            println!("{} has no labels", path.display());
+        } else {
+            println!("Labels of {}: {}", path.display(), labels);
+        }
+        Ok(())
+    }
+
+    async fn handle_export(&self, file: PathBuf, format: Option<PlaylistFormat>, filter: TagFilter, relative: bool) -> Result<()> {
+        let format = format
+            .or_else(|| PlaylistFormat::from_extension(&file))
+            .unwrap_or_default();
+        let queue = self.queue.lock().await;
+        let count = queue.export_playlist(&file, format, &filter, relative)?;
+
+        println!("Exported {} tracks to {}", count, file.display());
+        Ok(())
+    }
+
+    async fn handle_import(&self, file: PathBuf, append: bool) -> Result<()> {
+        let tags = TagStore::load(Self::get_tags_file_path())?;
+        let mut tracks = AudioQueue::read_playlist(&file)?;
+        for track in &mut tracks {
+            tags.apply(track);
+        }
+        println!("Read {} tracks from {}", tracks.len(), file.display());
+
+        let command = if append {
+            QueueCommand::AddTracks(tracks)
+        } else {
+            QueueCommand::ReplaceTracks(tracks)
+        };
+        self.queue_sender.send(command).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    async fn handle_duplicates(&self, policy: DuplicatePolicy, content: bool) -> Result<()> {
+        self.queue_sender.send(QueueCommand::SetDuplicatePolicy(policy, content)).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        println!("Duplicate policy set to {:?}{}", policy,
+            if content { " (matching file content)" } else { "" });
+        Ok(())
+    }
+
+    async fn handle_dedupe(&self) -> Result<()> {
+        let duplicates = {
+            let queue = self.queue.lock().await;
+            let duplicates = queue.duplicate_positions();
+            for &position in &duplicates {
+                println!("  pos {}: {}", position, queue.get_queue()[position]);
+            }
+            duplicates
+        };
+
+        if duplicates.is_empty() {
+            println!("No duplicate tracks in queue");
+            return Ok(());
+        }
+
+        self.queue_sender.send(QueueCommand::Dedupe).await?;
+
+        // Wait for the processor to handle the command
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Save state after modification
+        self.save_state().await?;
+
+        println!("Removed {} duplicate tracks", duplicates.len());
+
+        // Show updated queue
+        self.handle_list().await?;
+        Ok(())
+    }
+
+    async fn handle_snapshot(&self, action: SnapshotAction) -> Result<()> {
+        let library = SnapshotLibrary::new(Self::get_snapshot_dir());
+
+        match action {
+            SnapshotAction::Save { name } => {
+                let state = self.queue.lock().await.to_state();
+                let count = state.tracks.len();
+                library.save(&name, state)?;
+                println!("Saved snapshot '{}' with {} tracks", name, count);
+            }
+            SnapshotAction::List => {
+                let names = library.names()?;
+                if names.is_empty() {
+                    println!("No snapshots saved");
+                }
+                for name in names {
+                    let snapshot = library.load(&name)?;
+                    println!("  {} ({} tracks, {:?}, saved {})",
+                        name,
+                        snapshot.state.tracks.len(),
+                        snapshot.state.playback_state,
+                        snapshot.saved_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"));
+                }
+            }
+            SnapshotAction::Restore { name } => {
+                let snapshot = library.load(&name)?;
+                self.queue_sender.send(QueueCommand::Restore(Box::new(snapshot.state))).await?;
+
+                // Wait for the processor to handle the command
+                tokio::time::sleep(Duration::from_millis(50)).await;
+
+                // Save state after modification
+                self.save_state().await?;
+
+                println!("Restored snapshot '{}'", name);
+
+                // Show updated queue
+                self.handle_list().await?;
+            }
+            SnapshotAction::Delete { name } => {
+                library.delete(&name)?;
+                println!("Deleted snapshot '{}'", name);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_smart(&self, action: SmartAction) -> Result<()> {
+        let library = SmartPlaylistLibrary::new(Self::get_smart_playlist_dir());
+
+        match action {
+            SmartAction::List => {
+                let names = library.names()?;
+                if names.is_empty() {
+                    println!("No smart playlists saved");
+                }
+                for name in names {
+                    println!("  {}", name);
+                }
+            }
+            SmartAction::Show { name } => {
+                println!("{}", serde_json::to_string_pretty(&library.load(&name)?)?);
+            }
+            SmartAction::Save { name, definition } => {
+                library.save(&name, &definition.into_playlist())?;
+                println!("Saved smart playlist '{}'", name);
+            }
+            SmartAction::Delete { name } => {
+                library.delete(&name)?;
+                println!("Deleted smart playlist '{}'", name);
+            }
+            SmartAction::Run { name, append } => {
+                let playlist = library.load(&name)?;
+                let stats = StatsStore::load(Self::get_stats_file_path())?;
+                let tags = TagStore::load(Self::get_tags_file_path())?;
+
+                let mut tracks = playlist.evaluate(&stats)?;
+                for track in &mut tracks {
+                    tags.apply(track);
+                }
+                println!("Smart playlist '{}' matched {} tracks", name, tracks.len());
+
+                let command = if append {
+                    QueueCommand::AddTracks(tracks)
+                } else {
+                    QueueCommand::ReplaceTracks(tracks)
+                };
+                self.queue_sender.send(command).await?;
+
+                // Wait for the processor to handle the commands
+                tokio::time::sleep(Duration::from_millis(50)).await;
+
+                // Save state after modification
+                self.save_state().await?;
+
+                // Show updated queue
+                self.handle_list().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_queue(&self, action: QueueAction) -> Result<()> {
+        match action {
+            QueueAction::List => {
+                println!("{}", self.collection.lock().await.display());
+                return Ok(());
+            }
+            QueueAction::Create { name } => {
+                self.collection.lock().await.create(&name)?;
+                println!("Created queue '{}'", name);
+            }
+            QueueAction::Switch { name } => {
+                self.collection.lock().await.set_active(&name)?;
+                println!("Switched to queue '{}'", name);
+            }
+            QueueAction::Delete { name } => {
+                let mut collection = self.collection.lock().await;
+                collection.delete(&name)?;
+                println!("Deleted queue '{}', active queue is now '{}'", name, collection.active);
+            }
+            QueueAction::Copy { positions, to } => {
+                let tracks = self.selected_tracks(&positions, &to).await?;
+                let count = tracks.len();
+                self.collection.lock().await.append_tracks(&to, tracks)?;
+                println!("Copied {} tracks from '{}' to '{}'", count, self.queue_name, to);
+            }
+            QueueAction::Move { positions, to } => {
+                let tracks = self.selected_tracks(&positions, &to).await?;
+                let count = tracks.len();
+                self.collection.lock().await.append_tracks(&to, tracks)?;
+
+                self.queue_sender.send(QueueCommand::RemovePositions(positions)).await?;
+
+                // Wait for the processor to handle the command
+                tokio::time::sleep(Duration::from_millis(50)).await;
+
+                println!("Moved {} tracks from '{}' to '{}'", count, self.queue_name, to);
+            }
+        }
+
+        // Save state after modification
+        self.save_state().await?;
+        Ok(())
+    }
+
+    /// Tracks of the selected queue at the given positions, for transfer to `destination`
+    async fn selected_tracks(&self, positions: &[usize], destination: &str) -> Result<Vec<AudioTrack>> {
+        if destination == self.queue_name {
+            return Err(anyhow::anyhow!("Source and destination queue are the same"));
+        }
+        if !self.collection.lock().await.contains(destination) {
+            return Err(anyhow::anyhow!("Queue '{}' does not exist", destination));
+        }
+
+        let queue = self.queue.lock().await;
+        positions.iter()
+            .map(|&position| {
+                queue.get_queue().get(position)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("Position {} is out of bounds", position))
+            })
+            .collect()
+    }
+
     async fn handle_start(&self) -> Result<()> {
         println!("Starting AudioQueue daemon...");
         println!("AudioQueue daemon running. Press Ctrl+C to stop.");
@@ -605,20 +1955,29 @@ impl AudioQueueManager {
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-    let manager = AudioQueueManager::new().await?;
+    let manager = AudioQueueManager::new(cli.queue).await?;
 
     match cli.command {
-        Commands::Add { file, position } => {
-            manager.handle_add(file, position).await?;
+        Commands::Add { file, position, next, start, end, gain } => {
+            manager.handle_add(file, position, next, start, end, gain.unwrap_or(0.0)).await?;
         }
-        Commands::List => {
-            manager.handle_list().await?;
+        Commands::List { json, stats, min_plays, max_plays, min_skips } => {
+            if json {
+                manager.handle_list_json().await?;
+            } else if stats {
+                manager.handle_list_stats(StatsFilter { min_plays, max_plays, min_skips }).await?;
+            } else {
+                manager.handle_list().await?;
+            }
         }
-        Commands::Remove { position } => {
-            manager.handle_remove(position).await?;
+        Commands::Remove { positions, id } => {
+            manager.handle_remove(positions, id).await?;
         }
-        Commands::Move { from, to } => {
-            manager.handle_move(from, to).await?;
+        Commands::Move { from, to, id } => {
+            manager.handle_move(from, to, id).await?;
+        }
+        Commands::Keep { range } => {
+            manager.handle_keep(range).await?;
         }
         Commands::Play => {
             manager.handle_play().await?;
@@ -635,18 +1994,98 @@ async fn main() -> Result<()> {
         Commands::Previous => {
             manager.handle_previous().await?;
         }
-        Commands::Jump { position } => {
-            manager.handle_jump(position).await?;
+        Commands::Jump { position, id } => {
+            manager.handle_jump(position, id).await?;
         }
         Commands::Clear => {
             manager.handle_clear().await?;
         }
-        Commands::Status => {
-            manager.handle_status().await?;
+        Commands::Undo => {
+            manager.handle_undo().await?;
+        }
+        Commands::Redo => {
+            manager.handle_redo().await?;
+        }
+        Commands::Status { json } => {
+            if json {
+                manager.handle_status_json().await?;
+            } else {
+                manager.handle_status().await?;
+            }
+        }
+        Commands::StopAfter { position, id, clear } => {
+            manager.handle_stop_after(position, id, clear).await?;
         }
         Commands::Volume { level } => {
             manager.handle_volume(level).await?;
         }
+        Commands::Shuffle { mode } => {
+            manager.handle_shuffle(mode).await?;
+        }
+        Commands::Consume { mode } => {
+            manager.handle_consume(mode).await?;
+        }
+        Commands::Repeat { mode } => {
+            manager.handle_repeat(mode).await?;
+        }
+        Commands::History { enqueue } => {
+            manager.handle_history(enqueue).await?;
+        }
+        Commands::Find { query, field, regex, jump, remove, next, min_rating, labels } => {
+            let action = if jump {
+                FindAction::Jump
+            } else if remove {
+                FindAction::Remove
+            } else if next {
+                FindAction::PlayNext
+            } else {
+                FindAction::List
+            };
+            manager.handle_find(query, field, regex, TagFilter { min_rating, labels }, action).await?;
+        }
+        Commands::Trim { position, start, end, clear, id } => {
+            manager.handle_trim(position, id, start, end, clear).await?;
+        }
+        Commands::Gain { position, gain_db, id } => {
+            manager.handle_gain(position, id, gain_db).await?;
+        }
+        Commands::Rate { position, stars, id } => {
+            manager.handle_rate(position, id, stars).await?;
+        }
+        Commands::Label { position, labels, remove, clear, id } => {
+            let change = if clear {
+                LabelChange::Clear
+            } else if remove {
+                LabelChange::Remove(labels)
+            } else {
+                LabelChange::Add(labels)
+            };
+            manager.handle_label(position, id, change).await?;
+        }
+        Commands::Export { file, format, min_rating, labels, relative } => {
+            manager.handle_export(file, format, TagFilter { min_rating, labels }, relative).await?;
+        }
+        Commands::Import { file, append } => {
+            manager.handle_import(file, append).await?;
+        }
+        Commands::Sort { key, desc } => {
+            manager.handle_sort(key, desc).await?;
+        }
+        Commands::Duplicates { policy, content } => {
+            manager.handle_duplicates(policy, content).await?;
+        }
+        Commands::Dedupe => {
+            manager.handle_dedupe().await?;
+        }
+        Commands::Queue { action } => {
+            manager.handle_queue(action).await?;
+        }
+        Commands::Smart { action } => {
+            manager.handle_smart(action).await?;
+        }
+        Commands::Snapshot { action } => {
+            manager.handle_snapshot(action).await?;
+        }
         Commands::Start => {
             manager.handle_start().await?;
         }
@@ -0,0 +1,65 @@
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{Result, anyhow, Context};
+
+/// Items saved as `<name>.json` files in one directory, e.g. snapshots or smart
+/// playlist definitions
+#[derive(Debug, Clone)]
+pub struct NamedStore {
+    directory: PathBuf,
+    /// What the items are, capitalized, for messages such as "Snapshot"
+    kind: &'static str,
+}
+
+#[allow(dead_code)]
+impl NamedStore {
+    pub fn new<P: Into<PathBuf>>(directory: P, kind: &'static str) -> Self {
+        Self {
+            directory: directory.into(),
+            kind,
+        }
+    }
+
+    pub fn directory(&self) -> &PathBuf {
+        &self.directory
+    }
+
+    /// File of the item `name`. Names that would leave the directory are rejected.
+    pub fn path_of(&self, name: &str) -> Result<PathBuf> {
+        if name.trim().is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(anyhow!("Invalid {} name: '{}'", self.kind.to_lowercase(), name));
+        }
+        Ok(self.directory.join(format!("{}.json", name)))
+    }
+
+    /// File of the item `name`, which must exist
+    pub fn existing_path_of(&self, name: &str) -> Result<PathBuf> {
+        let path = self.path_of(name)?;
+        if !path.exists() {
+            return Err(anyhow!("{} '{}' does not exist", self.kind, name));
+        }
+        Ok(path)
+    }
+
+    /// Names of all items, sorted
+    pub fn names(&self) -> Result<Vec<String>> {
+        if !self.directory.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names: Vec<String> = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .filter_map(|path| path.file_stem().and_then(|stem| stem.to_str()).map(String::from))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let path = self.existing_path_of(name)?;
+        fs::remove_file(path)
+            .context(format!("Failed to delete {} '{}'", self.kind.to_lowercase(), name))
+    }
+}
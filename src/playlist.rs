@@ -0,0 +1,757 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use anyhow::{Result, anyhow, Context};
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Serialize, Deserialize};
+use url::Url;
+
+use crate::audio_queue::AudioTrack;
+
+/// One entry read from a playlist file, before it is resolved to a queued track.
+/// Fields the playlist doesn't provide are left unset.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlaylistEntry {
+    /// Path or URI exactly as written in the playlist
+    pub location: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<f64>,
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub gain_db: Option<f64>,
+}
+
+/// File formats playlists can be read from and written to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum PlaylistFormat {
+    /// Extended M3U, also used for `.m3u8`
+    #[default]
+    M3u,
+    /// `[playlist]` INI-style files used by radio tools
+    Pls,
+    /// XML Shareable Playlist Format, exported by desktop players
+    Xspf,
+    /// Cue sheet splitting one file into tracks. Can only be read.
+    #[value(skip)]
+    Cue,
+}
+
+impl PlaylistFormat {
+    /// Format implied by the file extension, if it is a known one
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            "cue" => Some(Self::Cue),
+            _ => None,
+        }
+    }
+
+    /// Format of a playlist file: its extension if known, otherwise its content
+    pub fn detect(path: &Path, content: &str) -> Self {
+        Self::from_extension(path).unwrap_or_else(|| Self::from_content(content))
+    }
+
+    /// Format recognized from the first meaningful line, M3U if nothing stands out
+    pub fn from_content(content: &str) -> Self {
+        let first = content.lines()
+            .map(|line| line.trim_start_matches('\u{feff}').trim())
+            .find(|line| !line.is_empty());
+        match first {
+            Some(line) if line.eq_ignore_ascii_case("[playlist]") => Self::Pls,
+            Some(line) if line.starts_with("<?xml") || line.starts_with("<playlist") => Self::Xspf,
+            _ => Self::M3u,
+        }
+    }
+
+    pub fn parse(self, content: &str) -> Result<Vec<PlaylistEntry>> {
+        match self {
+            Self::M3u => Ok(parse_m3u(content)),
+            Self::Pls => Ok(parse_pls(content)),
+            Self::Xspf => parse_xspf(content),
+            Self::Cue => Ok(parse_cue(content)),
+        }
+    }
+
+    /// Write `tracks` in this format, with locations relative to `base_dir` when it
+    /// is given. Tracks whose path can't be written are left out. Returns the number
+    /// of tracks written.
+    pub fn write<'a, W, I>(self, writer: &mut W, tracks: I, base_dir: Option<&Path>) -> Result<usize>
+    where
+        W: Write,
+        I: IntoIterator<Item = &'a AudioTrack>,
+    {
+        match self {
+            Self::M3u => write_m3u(writer, tracks, base_dir),
+            Self::Pls => write_pls(writer, tracks, base_dir),
+            Self::Xspf => write_xspf(writer, tracks, base_dir),
+            Self::Cue => Err(anyhow!("Cue sheets can only be read, not written")),
+        }
+    }
+
+    /// File an entry's location points to, relative locations being resolved against
+    /// `base_dir`. XSPF locations are URIs; other locations are paths, though
+    /// `file://` URIs are accepted too. None for locations that aren't local files.
+    pub fn resolve(self, location: &str, base_dir: &Path) -> Option<PathBuf> {
+        let base = || std::path::absolute(base_dir).ok()
+            .and_then(|dir| Url::from_directory_path(dir).ok());
+
+        if self == Self::Xspf {
+            return base()?.join(location).ok()
+                .filter(|url| url.scheme() == "file")?
+                .to_file_path().ok();
+        }
+
+        if location.get(..7).is_some_and(|scheme| scheme.eq_ignore_ascii_case("file://")) {
+            return Url::parse(location).ok()?.to_file_path().ok();
+        }
+        if location.contains("://") {
+            return None;
+        }
+
+        let path = Path::new(location);
+        Some(if path.is_absolute() { path.to_path_buf() } else { base_dir.join(path) })
+    }
+}
+
+/// Decode playlist bytes: UTF-8 (with or without a BOM), falling back to Latin-1
+/// for legacy `.m3u` files
+pub fn decode_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&byte| byte as char).collect(),
+    }
+}
+
+/// Parse an M3U or M3U8 playlist. `#EXTINF` and `#EXTVLCOPT` lines describe the
+/// entry that follows them; other directives and comments are ignored.
+pub fn parse_m3u(content: &str) -> Vec<PlaylistEntry> {
+    let mut entries = Vec::new();
+    let mut pending = PlaylistEntry::default();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            parse_extinf(info, &mut pending);
+        } else if let Some(option) = line.strip_prefix("#EXTVLCOPT:") {
+            parse_vlc_option(option, &mut pending);
+        } else if let Some(album) = line.strip_prefix("#EXTALB:") {
+            pending.album = Some(album.trim().to_string()).filter(|album| !album.is_empty());
+        } else if !line.starts_with('#') {
+            pending.location = line.to_string();
+            entries.push(std::mem::take(&mut pending));
+        }
+    }
+
+    entries
+}
+
+/// `#EXTINF:<seconds> [key="value" ...],<artist> - <title>`
+fn parse_extinf(info: &str, entry: &mut PlaylistEntry) {
+    // Attribute values may contain commas, so find the first one outside quotes
+    let mut in_quotes = false;
+    let split = info.char_indices().find(|&(_, c)| {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        c == ',' && !in_quotes
+    });
+    let (head, display) = match split {
+        Some((index, _)) => (&info[..index], info[index + 1..].trim()),
+        None => (info, ""),
+    };
+
+    // A negative length means unknown
+    entry.duration = head.split_whitespace()
+        .next()
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0);
+
+    set_display_title(display, entry);
+}
+
+/// Fill title and artist from a display title written as `Artist - Title`
+fn set_display_title(display: &str, entry: &mut PlaylistEntry) {
+    let display = display.trim();
+    if display.is_empty() {
+        return;
+    }
+
+    match display.split_once(" - ") {
+        Some((artist, title)) => {
+            entry.artist = Some(artist.trim().to_string());
+            entry.title = Some(title.trim().to_string());
+        }
+        None => entry.title = Some(display.to_string()),
+    }
+}
+
+/// Display title written for a track: `Artist - Title`, the title alone, or nothing
+fn display_title(track: &AudioTrack) -> String {
+    match (&track.artist, &track.title) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.clone(),
+        _ => String::new(),
+    }
+}
+
+/// `#EXTVLCOPT:<name>=<value>` options: trim points and gain
+fn parse_vlc_option(option: &str, entry: &mut PlaylistEntry) {
+    let Some((name, value)) = option.split_once('=') else {
+        return;
+    };
+    let Ok(value) = value.trim().parse::<f64>() else {
+        return;
+    };
+
+    match name.trim() {
+        "start-time" => entry.start = Some(value),
+        "stop-time" => entry.end = Some(value),
+        // VLC's gain option is a linear factor
+        "gain" if value > 0.0 => entry.gain_db = Some((20.0 * value.log10() * 100.0).round() / 100.0),
+        _ => {}
+    }
+}
+
+/// Path written for a track: relative to `base_dir` with `/` separators when given
+/// and possible, absolute otherwise. None, with a warning, for paths that aren't
+/// valid UTF-8, which M3U and PLS files can't hold.
+fn path_location(track: &AudioTrack, base_dir: Option<&Path>) -> Option<String> {
+    let Some(location) = track.path.to_str() else {
+        eprintln!("Warning: Skipping track with a path that is not valid UTF-8: {}", track.path.display());
+        return None;
+    };
+
+    let relative = base_dir.and_then(|base_dir| {
+        let path = std::path::absolute(&track.path).ok()?;
+        relative_path(&path, base_dir)
+    });
+    Some(relative.unwrap_or_else(|| location.to_string()))
+}
+
+/// `path` relative to the directory `base_dir`, both absolute, with `/` separators.
+/// None when there is no relative path, e.g. between drives on Windows.
+fn relative_path(path: &Path, base_dir: &Path) -> Option<String> {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base_dir.components().collect();
+    if path.first() != base.first() {
+        return None;
+    }
+
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut parts = vec![".."; base.len() - common];
+    for component in &path[common..] {
+        parts.push(component.as_os_str().to_str()?);
+    }
+    Some(parts.join("/"))
+}
+
+/// Write `tracks` as an extended M3U playlist
+pub fn write_m3u<'a, W, I>(writer: &mut W, tracks: I, base_dir: Option<&Path>) -> Result<usize>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a AudioTrack>,
+{
+    writeln!(writer, "#EXTM3U")?;
+
+    let mut count = 0;
+    for track in tracks {
+        let Some(mut location) = path_location(track, base_dir) else {
+            continue;
+        };
+        // A relative path starting with `#` would be read back as a comment
+        if location.starts_with('#') {
+            location.insert_str(0, "./");
+        }
+
+        if track.title.is_some() || track.duration.is_some() {
+            let seconds = track.duration.map_or(-1, |duration| duration.round() as i64);
+            writeln!(writer, "#EXTINF:{},{}", seconds, display_title(track))?;
+        }
+        if let Some(album) = &track.album {
+            writeln!(writer, "#EXTALB:{}", album)?;
+        }
+
+        // Trim points and gain use VLC's per-entry options
+        if let Some(start) = track.start {
+            writeln!(writer, "#EXTVLCOPT:start-time={}", start)?;
+        }
+        if let Some(end) = track.end {
+            writeln!(writer, "#EXTVLCOPT:stop-time={}", end)?;
+        }
+        if track.gain_db != 0.0 {
+            writeln!(writer, "#EXTVLCOPT:gain={}", track.gain_factor())?;
+        }
+
+        writeln!(writer, "{}", location)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Parse a PLS playlist. Entries are ordered by their number; `NumberOfEntries`
+/// is not trusted, since tools often get it wrong.
+pub fn parse_pls(content: &str) -> Vec<PlaylistEntry> {
+    let mut entries: BTreeMap<usize, PlaylistEntry> = BTreeMap::new();
+
+    for line in content.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+
+        // Keys are a field name followed by the entry number, e.g. `File3`
+        let digits = key.len() - key.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        let (field, number) = key.split_at(key.len() - digits);
+        let Ok(number) = number.parse::<usize>() else {
+            continue;
+        };
+
+        let entry = entries.entry(number).or_default();
+        match field {
+            "file" => entry.location = value.to_string(),
+            "title" => set_display_title(value, entry),
+            // A negative length means unknown, e.g. for streams
+            "length" => entry.duration = value.parse::<f64>().ok()
+                .filter(|seconds| seconds.is_finite() && *seconds >= 0.0),
+            _ => {}
+        }
+    }
+
+    entries.into_values()
+        .filter(|entry| !entry.location.is_empty())
+        .collect()
+}
+
+/// Write `tracks` as a PLS playlist. PLS has no place for albums, trim points or
+/// gain, so those are left out.
+pub fn write_pls<'a, W, I>(writer: &mut W, tracks: I, base_dir: Option<&Path>) -> Result<usize>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a AudioTrack>,
+{
+    writeln!(writer, "[playlist]")?;
+
+    let mut count = 0;
+    for track in tracks {
+        let Some(location) = path_location(track, base_dir) else {
+            continue;
+        };
+
+        count += 1;
+        writeln!(writer, "File{}={}", count, location)?;
+        let title = display_title(track);
+        if !title.is_empty() {
+            writeln!(writer, "Title{}={}", count, title)?;
+        }
+        writeln!(writer, "Length{}={}", count, track.duration.map_or(-1, |duration| duration.round() as i64))?;
+    }
+
+    writeln!(writer, "NumberOfEntries={}", count)?;
+    writeln!(writer, "Version=2")?;
+    Ok(count)
+}
+
+/// Application URI of VLC's XSPF extension, which carries per-track options
+const VLC_EXTENSION: &str = "http://www.videolan.org/vlc/playlist/0";
+
+/// Parse an XSPF playlist. `location` is kept as written, a URI reference to be
+/// resolved with `PlaylistFormat::resolve`; `duration` is in milliseconds.
+pub fn parse_xspf(content: &str) -> Result<Vec<PlaylistEntry>> {
+    let mut reader = Reader::from_str(content);
+    reader.config_mut().trim_text(true);
+
+    let mut entries = Vec::new();
+    let mut pending: Option<PlaylistEntry> = None;
+    // Local names of the open elements
+    let mut open: Vec<String> = Vec::new();
+
+    loop {
+        let event = reader.read_event()
+            .context(format!("Invalid XSPF at byte {}", reader.buffer_position()))?;
+        let text = match event {
+            Event::Start(element) => {
+                let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+                if name == "track" {
+                    pending = Some(PlaylistEntry::default());
+                }
+                open.push(name);
+                continue;
+            }
+            Event::End(_) => {
+                if open.pop().as_deref() == Some("track") {
+                    entries.extend(pending.take().filter(|entry| !entry.location.is_empty()));
+                }
+                continue;
+            }
+            Event::Text(text) => text.unescape()?.into_owned(),
+            Event::CData(data) => String::from_utf8_lossy(&data).into_owned(),
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let Some(entry) = pending.as_mut() else {
+            continue;
+        };
+        // Only direct children of <track> are track fields; VLC options sit deeper
+        let field = open.last().map(String::as_str);
+        let in_track = open.len() >= 2 && open[open.len() - 2] == "track";
+        match field {
+            Some("location") if in_track && entry.location.is_empty() => entry.location = text,
+            Some("title") if in_track => entry.title = Some(text),
+            Some("creator") if in_track => entry.artist = Some(text),
+            Some("album") if in_track => entry.album = Some(text),
+            Some("duration") if in_track => entry.duration = text.parse::<f64>().ok()
+                .filter(|millis| millis.is_finite() && *millis >= 0.0)
+                .map(|millis| millis / 1000.0),
+            Some("option") => parse_vlc_option(&text, entry),
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Write `tracks` as an XSPF playlist with `file://` locations, or percent-encoded
+/// URIs relative to `base_dir` when it is given. Trim points and gain are written
+/// as VLC options.
+pub fn write_xspf<'a, W, I>(writer: &mut W, tracks: I, base_dir: Option<&Path>) -> Result<usize>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a AudioTrack>,
+{
+    let base = base_dir.and_then(|base_dir| Url::from_directory_path(base_dir).ok());
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(writer, r#"<playlist version="1" xmlns="http://xspf.org/ns/0/" xmlns:vlc="{}">"#, VLC_EXTENSION)?;
+    writeln!(writer, "  <trackList>")?;
+
+    let mut count = 0;
+    for track in tracks {
+        let Some(url) = std::path::absolute(&track.path).ok()
+            .and_then(|path| Url::from_file_path(path).ok()) else {
+            continue;
+        };
+        let location = base.as_ref()
+            .and_then(|base| base.make_relative(&url))
+            .unwrap_or_else(|| url.into());
+
+        writeln!(writer, "    <track>")?;
+        writeln!(writer, "      <location>{}</location>", escape(&location))?;
+        for (element, value) in [("title", &track.title), ("creator", &track.artist), ("album", &track.album)] {
+            if let Some(value) = value {
+                writeln!(writer, "      <{0}>{1}</{0}>", element, escape(value.as_str()))?;
+            }
+        }
+        if let Some(duration) = track.duration {
+            writeln!(writer, "      <duration>{}</duration>", (duration * 1000.0).round() as u64)?;
+        }
+
+        let mut options = Vec::new();
+        if let Some(start) = track.start {
+            options.push(format!("start-time={}", start));
+        }
+        if let Some(end) = track.end {
+            options.push(format!("stop-time={}", end));
+        }
+        if track.gain_db != 0.0 {
+            options.push(format!("gain={}", track.gain_factor()));
+        }
+        if !options.is_empty() {
+            writeln!(writer, r#"      <extension application="{}">"#, VLC_EXTENSION)?;
+            for option in options {
+                writeln!(writer, "        <vlc:option>{}</vlc:option>", option)?;
+            }
+            writeln!(writer, "      </extension>")?;
+        }
+        writeln!(writer, "    </track>")?;
+        count += 1;
+    }
+
+    writeln!(writer, "  </trackList>")?;
+    writeln!(writer, "</playlist>")?;
+    Ok(count)
+}
+
+/// Cue sheet times are `MM:SS:FF`, with 75 frames per second
+const CUE_FRAMES_PER_SECOND: f64 = 75.0;
+
+fn parse_cue_time(time: &str) -> Option<f64> {
+    let mut parts = time.split(':').map(|part| part.parse::<u32>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || seconds >= 60 || frames >= CUE_FRAMES_PER_SECOND as u32 {
+        return None;
+    }
+    Some(minutes as f64 * 60.0 + seconds as f64 + frames as f64 / CUE_FRAMES_PER_SECOND)
+}
+
+/// First argument of a cue command, with the quotes around it removed
+fn cue_argument(rest: &str) -> String {
+    let rest = rest.trim();
+    match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or_default().to_string(),
+        None => rest.split_whitespace().next().unwrap_or_default().to_string(),
+    }
+}
+
+/// Parse a cue sheet into one entry per audio `TRACK`. A track plays from its
+/// `INDEX 01` to the next track's `INDEX 01` in the same file, the last one to the
+/// end of the file. `PERFORMER` and `TITLE` outside tracks are the album's.
+pub fn parse_cue(content: &str) -> Vec<PlaylistEntry> {
+    let mut album = None;
+    let mut album_performer = None;
+    let mut file = String::new();
+    // Audio tracks read so far; other track types, e.g. data, are left out
+    let mut tracks: Vec<PlaylistEntry> = Vec::new();
+    let mut in_track = false;
+    let mut in_audio_track = false;
+
+    for line in content.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                file = cue_argument(rest);
+                in_audio_track = false;
+            }
+            "TRACK" => {
+                in_track = true;
+                in_audio_track = rest.split_whitespace().nth(1)
+                    .is_some_and(|kind| kind.eq_ignore_ascii_case("AUDIO"));
+                if in_audio_track {
+                    tracks.push(PlaylistEntry { location: file.clone(), ..Default::default() });
+                }
+            }
+            "TITLE" if in_audio_track => tracks.last_mut().unwrap().title = Some(cue_argument(rest)),
+            "TITLE" if !in_track => album = Some(cue_argument(rest)),
+            "PERFORMER" if in_audio_track => tracks.last_mut().unwrap().artist = Some(cue_argument(rest)),
+            "PERFORMER" if !in_track => album_performer = Some(cue_argument(rest)),
+            "INDEX" if in_audio_track => {
+                let mut arguments = rest.split_whitespace();
+                if arguments.next().and_then(|number| number.parse::<u32>().ok()) == Some(1) {
+                    tracks.last_mut().unwrap().start = arguments.next().and_then(parse_cue_time);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Tracks without an INDEX 01 have no place in the file
+    tracks.retain(|track| track.start.is_some() && !track.location.is_empty());
+
+    for index in 0..tracks.len() {
+        let next_start = tracks.get(index + 1)
+            .filter(|next| next.location == tracks[index].location)
+            .and_then(|next| next.start);
+        let track = &mut tracks[index];
+        track.end = next_start;
+        track.album = album.clone();
+        if track.artist.is_none() {
+            track.artist = album_performer.clone();
+        }
+    }
+
+    tracks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_m3u() {
+        let content = "\u{feff}#EXTM3U\r\n\
+            #PLAYLIST:Show\r\n\
+            #EXTINF:185 tvg-name=\"a, b\",Daft Punk - One More Time\r\n\
+            #EXTVLCOPT:start-time=12.5\r\n\
+            music/one.mp3\r\n\
+            \r\n\
+            # a comment\r\n\
+            #EXTINF:-1,Jingle\r\n\
+            /jingles/id.wav\r\n\
+            bare.flac\r\n";
+        let entries = parse_m3u(&decode_text(content.as_bytes()));
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], PlaylistEntry {
+            location: "music/one.mp3".to_string(),
+            title: Some("One More Time".to_string()),
+            artist: Some("Daft Punk".to_string()),
+            duration: Some(185.0),
+            start: Some(12.5),
+            ..Default::default()
+        });
+        assert_eq!(entries[1].title.as_deref(), Some("Jingle"));
+        assert_eq!(entries[1].duration, None);
+        assert_eq!(entries[2], PlaylistEntry { location: "bare.flac".to_string(), ..Default::default() });
+
+        assert_eq!(decode_text(b"caf\xe9.mp3"), "café.mp3");
+    }
+
+    #[test]
+    fn test_parse_pls_and_detect() {
+        let content = "[playlist]\r\n\
+            File2=/music/two.mp3\r\n\
+            Title1=Artist - First\r\n\
+            File1=one.mp3\r\n\
+            Length1=61\r\n\
+            Length2=-1\r\n\
+            Title3=No file\r\n\
+            NumberOfEntries=5\r\n\
+            Version=2\r\n";
+        let entries = parse_pls(content);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].location, "one.mp3");
+        assert_eq!(entries[0].artist.as_deref(), Some("Artist"));
+        assert_eq!(entries[0].title.as_deref(), Some("First"));
+        assert_eq!(entries[0].duration, Some(61.0));
+        assert_eq!(entries[1], PlaylistEntry { location: "/music/two.mp3".to_string(), ..Default::default() });
+
+        assert_eq!(PlaylistFormat::detect(Path::new("list.PLS"), ""), PlaylistFormat::Pls);
+        assert_eq!(PlaylistFormat::detect(Path::new("list.txt"), content), PlaylistFormat::Pls);
+        assert_eq!(PlaylistFormat::detect(Path::new("list"), "#EXTM3U\n"), PlaylistFormat::M3u);
+    }
+
+    #[test]
+    fn test_parse_xspf_and_resolve() {
+        let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <playlist version="1" xmlns="http://xspf.org/ns/0/">
+              <title>Evening</title>
+              <trackList>
+                <track>
+                  <location>music/Caf%C3%A9%20Tacuba.flac</location>
+                  <title>Eres</title>
+                  <creator>Café Tacuba</creator>
+                  <album>Cuatro Caminos</album>
+                  <duration>245500</duration>
+                  <extension application="http://www.videolan.org/vlc/playlist/0">
+                    <vlc:option>start-time=3</vlc:option>
+                  </extension>
+                </track>
+                <track><location>http://radio.example/stream</location></track>
+                <track><title>No location</title></track>
+              </trackList>
+            </playlist>"#;
+        let entries = parse_xspf(content).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], PlaylistEntry {
+            location: "music/Caf%C3%A9%20Tacuba.flac".to_string(),
+            title: Some("Eres".to_string()),
+            artist: Some("Café Tacuba".to_string()),
+            album: Some("Cuatro Caminos".to_string()),
+            duration: Some(245.5),
+            start: Some(3.0),
+            ..Default::default()
+        });
+        assert_eq!(PlaylistFormat::from_content(content), PlaylistFormat::Xspf);
+
+        let base = Path::new("/lists");
+        let xspf = PlaylistFormat::Xspf;
+        assert_eq!(xspf.resolve(&entries[0].location, base), Some(PathBuf::from("/lists/music/Café Tacuba.flac")));
+        assert_eq!(xspf.resolve("file:///music/a%20b.mp3", base), Some(PathBuf::from("/music/a b.mp3")));
+        assert_eq!(xspf.resolve(&entries[1].location, base), None);
+        assert_eq!(PlaylistFormat::M3u.resolve("a b.mp3", base), Some(PathBuf::from("/lists/a b.mp3")));
+        assert_eq!(PlaylistFormat::M3u.resolve("file:///x/a%20b.mp3", base), Some(PathBuf::from("/x/a b.mp3")));
+
+        assert!(parse_xspf("<playlist><trackList><track></playlist>").is_err());
+    }
+
+    #[test]
+    fn test_write_relative_locations() -> Result<()> {
+        let track = |path: PathBuf| AudioTrack { path, ..Default::default() };
+        let root = std::path::absolute("/")?;
+        let tracks = vec![
+            track(root.join("music").join("a b").join("one.mp3")),
+            track(root.join("lists").join("#1.mp3")),
+        ];
+
+        let mut m3u = Vec::new();
+        assert_eq!(write_m3u(&mut m3u, &tracks, Some(&root.join("lists")))?, 2);
+        assert_eq!(String::from_utf8(m3u)?, "#EXTM3U\n../music/a b/one.mp3\n./#1.mp3\n");
+
+        let mut xspf = Vec::new();
+        write_xspf(&mut xspf, &tracks, Some(&root.join("lists")))?;
+        let xspf = String::from_utf8(xspf)?;
+        assert!(xspf.contains("<location>../music/a%20b/one.mp3</location>"));
+        assert!(xspf.contains("<location>%231.mp3</location>"));
+
+        // Without a base directory paths stay absolute
+        assert_eq!(path_location(&tracks[0], None).map(PathBuf::from), Some(tracks[0].path.clone()));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_skips_non_utf8_paths() -> Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+        let tracks = vec![
+            AudioTrack { path: PathBuf::from(std::ffi::OsStr::from_bytes(b"/music/caf\xe9.mp3")), ..Default::default() },
+            AudioTrack { path: PathBuf::from("/music/ok.mp3"), ..Default::default() },
+        ];
+
+        let mut pls = Vec::new();
+        assert_eq!(write_pls(&mut pls, &tracks, None)?, 1);
+        let pls = String::from_utf8(pls)?;
+        assert!(pls.contains("File1=/music/ok.mp3\n"));
+        assert!(pls.contains("NumberOfEntries=1\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cue() {
+        let content = "REM GENRE Electronic\r\n\
+            PERFORMER \"DJ Night\"\r\n\
+            TITLE \"Warehouse Mix\"\r\n\
+            FILE \"mix part 1.flac\" WAVE\r\n\
+            \x20 TRACK 01 AUDIO\r\n\
+            \x20   TITLE \"Opening\"\r\n\
+            \x20   INDEX 01 00:00:00\r\n\
+            \x20 TRACK 02 AUDIO\r\n\
+            \x20   TITLE \"Peak\"\r\n\
+            \x20   PERFORMER \"Guest\"\r\n\
+            \x20   INDEX 00 04:58:00\r\n\
+            \x20   INDEX 01 05:00:15\r\n\
+            FILE \"mix part 2.flac\" WAVE\r\n\
+            \x20 TRACK 03 AUDIO\r\n\
+            \x20   TITLE \"Closing\"\r\n\
+            \x20   INDEX 01 00:00:00\r\n";
+        let entries = parse_cue(content);
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], PlaylistEntry {
+            location: "mix part 1.flac".to_string(),
+            title: Some("Opening".to_string()),
+            artist: Some("DJ Night".to_string()),
+            album: Some("Warehouse Mix".to_string()),
+            start: Some(0.0),
+            end: Some(300.2),
+            ..Default::default()
+        });
+        assert_eq!(entries[1].artist.as_deref(), Some("Guest"));
+        assert_eq!(entries[1].start, Some(300.2));
+        // The last track of each file plays to its end
+        assert_eq!(entries[1].end, None);
+        assert_eq!(entries[2].location, "mix part 2.flac");
+        assert_eq!(entries[2].end, None);
+
+        assert_eq!(parse_cue_time("61:02:74"), Some(3662.0 + 74.0 / 75.0));
+        assert_eq!(parse_cue_time("01:60:00"), None);
+    }
+}
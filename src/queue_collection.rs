@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use anyhow::{Result, anyhow, Context};
+use serde::{Serialize, Deserialize};
+
+use crate::audio_queue::{AudioQueue, AudioQueueState, AudioTrack};
+use crate::track_tags::TrackTags;
+
+pub const DEFAULT_QUEUE_NAME: &str = "default";
+
+/// Named queues that are persisted together in a single state file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QueueCollection {
+    /// Queue used when no queue is selected explicitly
+    pub active: String,
+    pub queues: BTreeMap<String, AudioQueueState>,
+}
+
+#[allow(dead_code)]
+impl QueueCollection {
+    pub fn new() -> Self {
+        let mut queues = BTreeMap::new();
+        queues.insert(DEFAULT_QUEUE_NAME.to_string(), AudioQueue::new().to_state());
+
+        Self {
+            active: DEFAULT_QUEUE_NAME.to_string(),
+            queues,
+        }
+    }
+
+    /// Load all queues from file. A state file holding a single queue, as written
+    /// by older versions, is migrated into the default queue.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+
+        let content = fs::read_to_string(path)
+            .context("Failed to read queue state file")?;
+
+        if let Ok(collection) = serde_json::from_str::<QueueCollection>(&content) {
+            if collection.queues.contains_key(&collection.active) {
+                return Ok(collection);
+            }
+        }
+
+        let state: AudioQueueState = serde_json::from_str(&content)
+            .context("Failed to parse queue state file")?;
+
+        let mut collection = Self::new();
+        collection.queues.insert(DEFAULT_QUEUE_NAME.to_string(), state);
+        Ok(collection)
+    }
+
+    /// Save all queues to file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let content = serde_json::to_string_pretty(self)
+            .context("Failed to serialize queue state")?;
+
+        // Create parent directories if they don't exist
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .context("Failed to create queue state directory")?;
+        }
+
+        fs::write(path, content)
+            .context("Failed to write queue state file")
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.queues.keys()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.queues.contains_key(name)
+    }
+
+    pub fn create(&mut self, name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(anyhow!("Queue name cannot be empty"));
+        }
+        if self.contains(name) {
+            return Err(anyhow!("Queue '{}' already exists", name));
+        }
+
+        self.queues.insert(name.to_string(), AudioQueue::new().to_state());
+        Ok(())
+    }
+
+    /// Delete a queue. Deleting the active queue activates the first remaining
+    /// one, and an empty default queue is recreated if none are left.
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        if self.queues.remove(name).is_none() {
+            return Err(anyhow!("Queue '{}' does not exist", name));
+        }
+
+        if self.queues.is_empty() {
+            self.queues.insert(DEFAULT_QUEUE_NAME.to_string(), AudioQueue::new().to_state());
+        }
+
+        if self.active == name {
+            self.active = self.queues.keys().next().cloned().unwrap_or_default();
+        }
+
+        Ok(())
+    }
+
+    pub fn set_active(&mut self, name: &str) -> Result<()> {
+        if !self.contains(name) {
+            return Err(anyhow!("Queue '{}' does not exist", name));
+        }
+
+        self.active = name.to_string();
+        Ok(())
+    }
+
+    /// Build a live queue from the stored state of a named queue
+    pub fn queue(&self, name: &str) -> Result<AudioQueue> {
+        self.queues.get(name)
+            .cloned()
+            .map(AudioQueue::from_state)
+            .ok_or_else(|| anyhow!("Queue '{}' does not exist", name))
+    }
+
+    /// Store the state of a live queue under the given name
+    pub fn store(&mut self, name: &str, queue: &AudioQueue) {
+        self.queues.insert(name.to_string(), queue.to_state());
+    }
+
+    /// Append tracks to the end of a named queue. Tracks get fresh IDs there.
+    pub fn append_tracks(&mut self, name: &str, tracks: Vec<AudioTrack>) -> Result<()> {
+        let mut queue = self.queue(name)?;
+        for track in tracks {
+            queue.add_track(track, None)?;
+        }
+
+        self.store(name, &queue);
+        Ok(())
+    }
+
+    /// Give every queued copy of the file at `path`, in all queues, these tags.
+    /// Returns the number of tracks updated.
+    pub fn set_tags(&mut self, path: &Path, tags: &TrackTags) -> usize {
+        let mut updated = 0;
+        for state in self.queues.values_mut() {
+            let mut queue = AudioQueue::from_state(state.clone());
+            let count = queue.set_tags(path, tags);
+            if count > 0 {
+                *state = queue.to_state();
+                updated += count;
+            }
+        }
+        updated
+    }
+
+    pub fn display(&self) -> String {
+        let mut output = String::new();
+        output.push_str("Queues:\n");
+
+        for (name, state) in &self.queues {
+            let marker = if *name == self.active { "* " } else { "  " };
+            output.push_str(&format!("{}{} ({} tracks)\n", marker, name, state.tracks.len()));
+        }
+
+        output
+    }
+}
+
+impl Default for QueueCollection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn track(path: &str) -> AudioTrack {
+        AudioTrack {
+            path: PathBuf::from(path),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_create_switch_delete() {
+        let mut collection = QueueCollection::new();
+        collection.create("jingles").unwrap();
+        assert!(collection.create("jingles").is_err());
+
+        collection.set_active("jingles").unwrap();
+        assert_eq!(collection.active, "jingles");
+
+        collection.delete("jingles").unwrap();
+        assert_eq!(collection.active, DEFAULT_QUEUE_NAME);
+
+        collection.delete(DEFAULT_QUEUE_NAME).unwrap();
+        assert!(collection.contains(DEFAULT_QUEUE_NAME));
+    }
+
+    #[test]
+    fn test_append_and_legacy_migration() {
+        let mut collection = QueueCollection::new();
+        collection.create("requests").unwrap();
+        collection.append_tracks("requests", vec![track("a.mp3"), track("b.mp3")]).unwrap();
+        assert_eq!(collection.queue("requests").unwrap().get_queue().len(), 2);
+
+        // Tags reach copies of a file in every queue
+        collection.append_tracks(DEFAULT_QUEUE_NAME, vec![track("b.mp3")]).unwrap();
+        let tags = TrackTags { rating: Some(4), ..Default::default() };
+        assert_eq!(collection.set_tags(Path::new("b.mp3"), &tags), 2);
+        assert_eq!(collection.queue(DEFAULT_QUEUE_NAME).unwrap().get_queue()[0].rating, Some(4));
+
+        // A single-queue state file becomes the default queue
+        let mut legacy = AudioQueue::new();
+        legacy.add_track(track("c.mp3"), None).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        legacy.save_state(&path).unwrap();
+
+        let migrated = QueueCollection::load(&path).unwrap();
+        assert_eq!(migrated.queue(DEFAULT_QUEUE_NAME).unwrap().get_queue().len(), 1);
+    }
+}
@@ -1,14 +1,20 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 use anyhow::{Result, anyhow};
+use chrono::Utc;
 use tokio::sync::{Mutex, mpsc::Receiver};
 use tokio::time::{interval, Duration};
-use crate::audio_queue::{AudioQueue, QueueCommand, PlaybackState};
+use crate::audio_queue::{AddOutcome, AudioQueue, AudioTrack, QueueCommand, PlaybackState};
 use crate::audio_emitter::EmitterCommand;
+use crate::track_stats::StatsStore;
 
 pub struct QueueProcessor {
     queue: Arc<Mutex<AudioQueue>>,
     emitter_sender: tokio::sync::mpsc::Sender<EmitterCommand>,
     command_receiver: Option<Receiver<QueueCommand>>,
+    stats: Arc<Mutex<StatsStore>>,
+    /// File the play statistics are saved to after every update
+    stats_file: Option<PathBuf>,
 }
 
 impl QueueProcessor {
@@ -21,6 +27,54 @@ impl QueueProcessor {
             queue,
             emitter_sender,
             command_receiver: Some(command_receiver),
+            stats: Arc::new(Mutex::new(StatsStore::new())),
+            stats_file: None,
+        }
+    }
+
+    /// Keep play statistics in `path`, loading the ones recorded so far
+    pub fn with_stats_file(mut self, path: PathBuf) -> Self {
+        let stats = StatsStore::load(&path).unwrap_or_else(|e| {
+            eprintln!("Warning: {}, starting new play statistics", e);
+            StatsStore::new()
+        });
+        self.stats = Arc::new(Mutex::new(stats));
+        self.stats_file = Some(path);
+        self
+    }
+
+    /// The current track and how long it has played, if its playback has started
+    fn started_track(queue: &AudioQueue) -> Option<(AudioTrack, f64)> {
+        if queue.playback_state == PlaybackState::Stopped {
+            return None;
+        }
+        queue.get_current_track()
+            .cloned()
+            .map(|track| (track, queue.elapsed(Utc::now())))
+    }
+
+    /// Update the statistics after navigating away from `before`: it counts as
+    /// skipped, and the new current track as started if playback goes on
+    async fn record_track_change(&self, before: Option<(AudioTrack, f64)>, queue: &AudioQueue) -> Result<()> {
+        let after = queue.get_current_track();
+        if before.as_ref().map(|(track, _)| track.id) == after.map(|track| track.id) {
+            return Ok(());
+        }
+
+        let mut stats = self.stats.lock().await;
+        if let Some((track, listened)) = before {
+            stats.record_skip(&track.path, listened);
+        }
+        if let Some(track) = after.filter(|_| queue.playback_state == PlaybackState::Playing) {
+            stats.record_start(&track.path, Utc::now());
+        }
+        self.save_stats(&stats)
+    }
+
+    fn save_stats(&self, stats: &StatsStore) -> Result<()> {
+        match &self.stats_file {
+            Some(path) => stats.save(path),
+            None => Ok(()),
         }
     }
 
@@ -57,8 +111,37 @@ impl QueueProcessor {
         match command {
             QueueCommand::Add(track, position) => {
                 let mut queue = self.queue.lock().await;
-                queue.add_track(track, position)?;
-                println!("Track added to queue");
+                match queue.add_track(track, position)? {
+                    AddOutcome::Added(_) => println!("Track added to queue"),
+                    AddOutcome::Skipped(existing) => {
+                        println!("Track already queued at position {}, skipped", existing);
+                    }
+                    AddOutcome::Moved { from, to } => {
+                        println!("Track already queued, moved from position {} to {}", from, to);
+                    }
+                }
+            }
+            QueueCommand::AddTracks(tracks) => {
+                let mut queue = self.queue.lock().await;
+                let total = tracks.len();
+                let added = queue.add_tracks(tracks);
+                println!("{} of {} tracks added to queue", added, total);
+            }
+            QueueCommand::ReplaceTracks(tracks) => {
+                let mut queue = self.queue.lock().await;
+                let total = tracks.len();
+                let added = queue.replace_tracks(tracks);
+                println!("Queue replaced, {} of {} tracks added", added, total);
+            }
+            QueueCommand::AddNext(track) => {
+                let mut queue = self.queue.lock().await;
+                queue.add_next(track);
+                println!("Track added to up next");
+            }
+            QueueCommand::PlayNext(positions) => {
+                let mut queue = self.queue.lock().await;
+                queue.promote_to_up_next(&positions)?;
+                println!("Tracks moved to up next");
             }
             QueueCommand::Remove(position) => {
                 let mut queue = self.queue.lock().await;
@@ -70,6 +153,37 @@ impl QueueProcessor {
                 queue.move_track(from, to)?;
                 println!("Track moved in queue");
             }
+            QueueCommand::RemovePositions(positions) => {
+                let mut queue = self.queue.lock().await;
+                let removed = queue.remove_positions(&positions)?;
+                println!("{} tracks removed from queue", removed.len());
+            }
+            QueueCommand::RemoveRange(range) => {
+                let mut queue = self.queue.lock().await;
+                let removed = queue.remove_range(range)?;
+                println!("{} tracks removed from queue", removed.len());
+            }
+            QueueCommand::KeepRange(range) => {
+                let mut queue = self.queue.lock().await;
+                let removed = queue.keep_range(range)?;
+                println!("{} tracks removed from queue", removed.len());
+            }
+            QueueCommand::MoveBlock(range, to) => {
+                let mut queue = self.queue.lock().await;
+                let count = range.len();
+                queue.move_block(range, to)?;
+                println!("{} tracks moved in queue", count);
+            }
+            QueueCommand::RemoveById(id) => {
+                let mut queue = self.queue.lock().await;
+                queue.remove_track_by_id(id)?;
+                println!("Track {} removed from queue", id);
+            }
+            QueueCommand::MoveById(id, to) => {
+                let mut queue = self.queue.lock().await;
+                queue.move_track_by_id(id, to)?;
+                println!("Track {} moved in queue", id);
+            }
             QueueCommand::Play => {
                 let mut queue = self.queue.lock().await;
 
@@ -79,11 +193,20 @@ impl QueueProcessor {
                     let _ = queue.jump_to(0);
                 }
 
+                let was_playing = queue.playback_state == PlaybackState::Playing;
                 queue.play()?;
 
+                if !was_playing {
+                    if let Some(track) = queue.get_current_track() {
+                        let mut stats = self.stats.lock().await;
+                        stats.record_start(&track.path, Utc::now());
+                        self.save_stats(&stats)?;
+                    }
+                }
+
                 // Get the current track and send it to the emitter
                 if let Some(track) = queue.get_current_track() {
-                    let file_path = track.path.to_string_lossy().to_string();
+                    let track = track.clone();
 
                     // Send play command to emitter
                     if let Err(e) = emitter_sender.send(EmitterCommand::Stop).await {
@@ -93,7 +216,7 @@ impl QueueProcessor {
                     // Small delay before sending play
                     tokio::time::sleep(Duration::from_millis(50)).await;
 
-                    if let Err(e) = emitter_sender.send(EmitterCommand::Play(file_path)).await {
+                    if let Err(e) = emitter_sender.send(EmitterCommand::PlayTrack(track)).await {
                         eprintln!("Error sending play command: {}", e);
                     }
                 }
@@ -112,8 +235,10 @@ impl QueueProcessor {
             }
             QueueCommand::Next => {
                 let mut queue = self.queue.lock().await;
+                let before = Self::started_track(&queue);
 
                 if queue.next_track().is_ok() {
+                    self.record_track_change(before, &queue).await?;
                     println!("Next track");
                 } else {
                     println!("Already at last track or queue is empty");
@@ -121,8 +246,10 @@ impl QueueProcessor {
             }
             QueueCommand::Previous => {
                 let mut queue = self.queue.lock().await;
+                let before = Self::started_track(&queue);
 
                 if queue.previous().is_ok() {
+                    self.record_track_change(before, &queue).await?;
                     println!("Previous track");
                 } else {
                     println!("Already at first track or queue is empty");
@@ -130,18 +257,47 @@ impl QueueProcessor {
             }
             QueueCommand::Jump(position) => {
                 let mut queue = self.queue.lock().await;
+                let before = Self::started_track(&queue);
 
                 if queue.jump_to(position).is_ok() {
+                    self.record_track_change(before, &queue).await?;
                     println!("Jumped to position {}", position);
                 } else {
                     println!("Invalid position or queue is empty");
                 }
             }
+            QueueCommand::JumpById(id) => {
+                let mut queue = self.queue.lock().await;
+                let before = Self::started_track(&queue);
+
+                if queue.jump_to_id(id).is_ok() {
+                    self.record_track_change(before, &queue).await?;
+                    println!("Jumped to track {}", id);
+                } else {
+                    println!("No track with ID {}", id);
+                }
+            }
             QueueCommand::Clear => {
                 let mut queue = self.queue.lock().await;
                 queue.clear()?;
                 println!("Queue cleared");
             }
+            QueueCommand::Sort(key, order) => {
+                let mut queue = self.queue.lock().await;
+                let stats = self.stats.lock().await;
+                queue.sort_by_with_stats(key, order, &stats);
+                println!("Queue sorted by {:?} ({:?})", key, order);
+            }
+            QueueCommand::Undo => {
+                let mut queue = self.queue.lock().await;
+                let operation = queue.undo()?;
+                println!("Undid {}", operation);
+            }
+            QueueCommand::Redo => {
+                let mut queue = self.queue.lock().await;
+                let operation = queue.redo()?;
+                println!("Redid {}", operation);
+            }
             QueueCommand::GetStatus => {
                 let queue = self.queue.lock().await;
                 let (state, current_track, queue_size) = queue.get_status();
@@ -160,6 +316,66 @@ impl QueueProcessor {
                 }
                 println!("======================");
             }
+            QueueCommand::SetShuffle(enabled) => {
+                let mut queue = self.queue.lock().await;
+                queue.set_shuffle(enabled);
+                println!("Shuffle {}", if enabled { "enabled" } else { "disabled" });
+            }
+            QueueCommand::SetConsume(enabled) => {
+                let mut queue = self.queue.lock().await;
+                queue.set_consume(enabled);
+                println!("Consume mode {}", if enabled { "enabled" } else { "disabled" });
+            }
+            QueueCommand::SetRepeat(mode) => {
+                let mut queue = self.queue.lock().await;
+                queue.set_repeat_mode(mode);
+                println!("Repeat mode set to {:?}", mode);
+            }
+            QueueCommand::SetDuplicatePolicy(policy, match_content) => {
+                let mut queue = self.queue.lock().await;
+                queue.set_duplicate_policy(policy, match_content);
+                println!("Duplicate policy set to {:?}", policy);
+            }
+            QueueCommand::SetTags(path, tags) => {
+                let mut queue = self.queue.lock().await;
+                let updated = queue.set_tags(&path, &tags);
+                println!("Updated tags of {} tracks", updated);
+            }
+            QueueCommand::Restore(state) => {
+                let mut queue = self.queue.lock().await;
+                queue.restore(*state);
+                println!("Queue restored with {} tracks", queue.get_queue().len());
+            }
+            QueueCommand::SetTrim(position, start, end) => {
+                let mut queue = self.queue.lock().await;
+                let track = queue.set_trim(position, start, end)?;
+                println!("Track at position {} now plays {:.1}s", position, track.playable_duration().unwrap_or(0.0));
+            }
+            QueueCommand::SetGain(position, gain_db) => {
+                let mut queue = self.queue.lock().await;
+                queue.set_gain(position, gain_db)?;
+                println!("Gain of track at position {} set to {:+.1} dB", position, gain_db);
+
+                // Apply the new gain right away if this track is playing
+                if queue.current_position == Some(position) && queue.playback_state == PlaybackState::Playing {
+                    if let Err(e) = emitter_sender.send(EmitterCommand::TrackGain(gain_db)).await {
+                        eprintln!("Error sending gain command: {}", e);
+                    }
+                }
+            }
+            QueueCommand::SetStopAfter(id) => {
+                let mut queue = self.queue.lock().await;
+                queue.set_stop_after(id)?;
+                match id {
+                    Some(id) => println!("Playback will stop after track {}", id),
+                    None => println!("Stop marker cleared"),
+                }
+            }
+            QueueCommand::Dedupe => {
+                let mut queue = self.queue.lock().await;
+                let removed = queue.dedupe();
+                println!("Removed {} duplicate tracks", removed.len());
+            }
         }
 
         Ok(())
@@ -173,6 +389,7 @@ impl QueueProcessor {
         // Get current queue state
         let queue = self.queue.lock().await;
         let (playback_state, current_track, queue_size) = queue.get_status();
+        let ended = queue.track_ended(Utc::now());
         drop(queue);
 
         // Only process if we have tracks
@@ -182,15 +399,20 @@ impl QueueProcessor {
         }
 
         // If we're currently playing, mark it
-        if playback_state == PlaybackState::Playing && current_track.is_some() {
+        if playback_state == PlaybackState::Playing && current_track.is_some() && !ended {
             *was_playing = true;
             return Ok(());
         }
 
-        // If we were playing but now we're not, try to advance
-        if *was_playing && (playback_state != PlaybackState::Playing || current_track.is_none()) {
+        // If the track ended, or we were playing but now we're not, try to advance
+        if ended || (*was_playing && (playback_state != PlaybackState::Playing || current_track.is_none())) {
             *was_playing = false;
 
+            // A paused track resumes where it left off, so it hasn't finished
+            if playback_state == PlaybackState::Paused {
+                return Ok(());
+            }
+
             println!("🎵 Track finished, advancing to next...");
 
             // Stop current playback
@@ -201,9 +423,35 @@ impl QueueProcessor {
             // Small delay before advancing
             tokio::time::sleep(Duration::from_millis(100)).await;
 
-            // Try to advance to next track
-            if let Err(e) = self.handle_command(QueueCommand::Next, emitter_sender).await {
-                eprintln!("Error advancing to next track: {}", e);
+            // Advance according to the repeat mode
+            let advanced = {
+                let mut queue = self.queue.lock().await;
+
+                if let Some(track) = queue.get_current_track().filter(|_| ended) {
+                    let listened = track.playable_duration().unwrap_or_else(|| queue.elapsed(Utc::now()));
+                    let mut stats = self.stats.lock().await;
+                    stats.record_finish(&track.path, listened);
+                    if let Err(e) = self.save_stats(&stats) {
+                        eprintln!("Error saving play statistics: {}", e);
+                    }
+                }
+
+                // A stop marker ends playback cleanly instead of advancing, but
+                // only once its track really played to the end
+                if ended && queue.stop_at_marker() {
+                    println!("⏹ Stop marker reached, playback stopped");
+                    return Ok(());
+                }
+
+                let result = queue.advance_after_finish();
+                if result.is_err() {
+                    queue.playback_state = PlaybackState::Stopped;
+                }
+                result
+            };
+
+            if let Err(e) = advanced {
+                println!("🎵 {}, stopping playback", e);
             } else {
                 // After successfully advancing, try to play the next track
                 tokio::time::sleep(Duration::from_millis(50)).await;
@@ -216,3 +464,105 @@ impl QueueProcessor {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_pause_keeps_consumed_track() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for i in 1..=2 {
+            let track = AudioTrack {
+                path: PathBuf::from(format!("test{}.mp3", i)),
+                duration: Some(60.0),
+                ..Default::default()
+            };
+            queue.add_track(track, None)?;
+        }
+        queue.set_consume(true);
+        queue.play()?;
+
+        let (emitter_sender, _emitter_receiver) = mpsc::channel(16);
+        let (_queue_sender, queue_receiver) = mpsc::channel(16);
+        let processor = QueueProcessor::new(Arc::new(Mutex::new(queue)), emitter_sender.clone(), queue_receiver);
+
+        let mut was_playing = false;
+        processor.check_track_finished(&emitter_sender, &mut was_playing).await?;
+        processor.queue.lock().await.pause()?;
+        processor.check_track_finished(&emitter_sender, &mut was_playing).await?;
+
+        let queue = processor.queue.lock().await;
+        assert_eq!(queue.get_queue().len(), 2);
+        assert_eq!(queue.current_position, Some(0));
+        assert_eq!(queue.playback_state, PlaybackState::Paused);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stop_marker_waits_for_track_end() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for i in 1..=2 {
+            let track = AudioTrack {
+                path: PathBuf::from(format!("test{}.mp3", i)),
+                duration: Some(0.2),
+                ..Default::default()
+            };
+            queue.add_track(track, None)?;
+        }
+        queue.play()?;
+        let first = queue.get_current_track().unwrap().id;
+        queue.set_stop_after(Some(first))?;
+
+        let (emitter_sender, _emitter_receiver) = mpsc::channel(16);
+        let (_queue_sender, queue_receiver) = mpsc::channel(16);
+        let processor = QueueProcessor::new(Arc::new(Mutex::new(queue)), emitter_sender.clone(), queue_receiver);
+
+        let mut was_playing = false;
+        processor.check_track_finished(&emitter_sender, &mut was_playing).await?;
+        processor.queue.lock().await.pause()?;
+        processor.check_track_finished(&emitter_sender, &mut was_playing).await?;
+        assert_eq!(processor.queue.lock().await.stop_after(), Some(first));
+
+        processor.queue.lock().await.resume()?;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        processor.check_track_finished(&emitter_sender, &mut was_playing).await?;
+
+        let queue = processor.queue.lock().await;
+        assert_eq!(queue.stop_after(), None);
+        assert_eq!(queue.playback_state, PlaybackState::Stopped);
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_finish_is_recorded_at_track_end() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for i in 1..=3 {
+            let track = AudioTrack {
+                path: PathBuf::from(format!("test{}.mp3", i)),
+                duration: Some(0.2),
+                ..Default::default()
+            };
+            queue.add_track(track, None)?;
+        }
+        queue.play()?;
+
+        let (emitter_sender, _emitter_receiver) = mpsc::channel(16);
+        let (_queue_sender, queue_receiver) = mpsc::channel(16);
+        let processor = QueueProcessor::new(Arc::new(Mutex::new(queue)), emitter_sender.clone(), queue_receiver);
+        let listened = |stats: &StatsStore, path: &str| stats.get(path).listened;
+
+        // Playback stopping early is not a finish
+        let mut was_playing = false;
+        processor.check_track_finished(&emitter_sender, &mut was_playing).await?;
+        processor.queue.lock().await.playback_state = PlaybackState::Stopped;
+        processor.check_track_finished(&emitter_sender, &mut was_playing).await?;
+        assert_eq!(listened(&*processor.stats.lock().await, "test1.mp3"), 0.0);
+
+        processor.check_track_finished(&emitter_sender, &mut was_playing).await?;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        processor.check_track_finished(&emitter_sender, &mut was_playing).await?;
+        assert_eq!(listened(&*processor.stats.lock().await, "test2.mp3"), 0.2);
+        Ok(())
+    }
+}
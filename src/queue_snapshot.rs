@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{Result, Context};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::audio_queue::AudioQueueState;
+use crate::named_store::NamedStore;
+
+/// The complete state of a queue at the time it was saved
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QueueSnapshot {
+    pub saved_at: DateTime<Utc>,
+    pub state: AudioQueueState,
+}
+
+/// Named snapshots saved as `<name>.json` files in one directory
+pub struct SnapshotLibrary {
+    store: NamedStore,
+}
+
+#[allow(dead_code)]
+impl SnapshotLibrary {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            store: NamedStore::new(directory, "Snapshot"),
+        }
+    }
+
+    /// Names of all snapshots, sorted
+    pub fn names(&self) -> Result<Vec<String>> {
+        self.store.names()
+    }
+
+    /// Save a queue state under `name`, replacing an existing snapshot of that name
+    pub fn save(&self, name: &str, state: AudioQueueState) -> Result<QueueSnapshot> {
+        let path = self.store.path_of(name)?;
+        let snapshot = QueueSnapshot {
+            saved_at: Utc::now(),
+            state,
+        };
+
+        let content = serde_json::to_string_pretty(&snapshot)
+            .context("Failed to serialize snapshot")?;
+        fs::create_dir_all(self.store.directory())
+            .context("Failed to create snapshot directory")?;
+        fs::write(&path, content)
+            .context(format!("Failed to write snapshot '{}'", name))?;
+
+        Ok(snapshot)
+    }
+
+    pub fn load(&self, name: &str) -> Result<QueueSnapshot> {
+        let path = self.store.existing_path_of(name)?;
+        let content = fs::read_to_string(&path)
+            .context(format!("Failed to read snapshot '{}'", name))?;
+        serde_json::from_str(&content)
+            .context(format!("Failed to parse snapshot '{}'", name))
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.store.delete(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_queue::{AudioQueue, AudioTrack, PlaybackState};
+
+    #[test]
+    fn test_save_restore_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = SnapshotLibrary::new(dir.path());
+
+        let mut queue = AudioQueue::new();
+        for path in ["a.mp3", "b.mp3"] {
+            let track = AudioTrack { path: PathBuf::from(path), ..Default::default() };
+            queue.add_track(track, None).unwrap();
+        }
+        queue.jump_to(1).unwrap();
+        queue.play().unwrap();
+
+        library.save("before-show", queue.to_state()).unwrap();
+        assert!(library.save("", queue.to_state()).is_err());
+        assert_eq!(library.names().unwrap(), ["before-show"]);
+
+        queue.clear().unwrap();
+        queue.restore(library.load("before-show").unwrap().state);
+        assert_eq!(queue.get_queue().len(), 2);
+        assert_eq!(queue.current_position, Some(1));
+        assert_eq!(queue.playback_state, PlaybackState::Playing);
+
+        library.delete("before-show").unwrap();
+        assert!(library.load("before-show").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Serialize, Deserialize};
+
+use crate::audio_queue::{AudioQueue, AudioTrack, SortKey, SortOrder};
+use crate::named_store::NamedStore;
+use crate::track_stats::StatsStore;
+
+/// How the rules of a smart playlist are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleMatch {
+    /// A file must match every rule
+    #[default]
+    All,
+    /// A file must match at least one rule
+    Any,
+}
+
+/// Condition on a file or its metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Rule {
+    /// Artist contains the text, ignoring case
+    Artist(String),
+    /// Title contains the text, ignoring case
+    Title(String),
+    /// Duration in seconds lies within the bounds
+    Duration { min: Option<f64>, max: Option<f64> },
+    /// File extension is one of these, ignoring case
+    Extension(Vec<String>),
+    /// Full path matches a glob pattern such as `**/live/*.flac`
+    PathGlob(String),
+    /// File was last modified within the dates, both inclusive
+    Modified { after: Option<NaiveDate>, before: Option<NaiveDate> },
+}
+
+impl Rule {
+    /// Whether the rule looks at metadata that has to be probed from the file
+    fn needs_metadata(&self) -> bool {
+        matches!(self, Rule::Artist(_) | Rule::Title(_) | Rule::Duration { .. })
+    }
+
+    pub fn matches(&self, track: &AudioTrack) -> Result<bool> {
+        let contains = |value: &Option<String>, text: &str| {
+            value.as_ref().is_some_and(|value| value.to_lowercase().contains(&text.to_lowercase()))
+        };
+
+        Ok(match self {
+            Rule::Artist(text) => contains(&track.artist, text),
+            Rule::Title(text) => contains(&track.title, text),
+            Rule::Duration { min, max } => track.duration.is_some_and(|duration| {
+                min.is_none_or(|min| duration >= min) && max.is_none_or(|max| duration <= max)
+            }),
+            Rule::Extension(extensions) => track.path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    extensions.iter().any(|wanted| wanted.trim_start_matches('.').eq_ignore_ascii_case(extension))
+                }),
+            Rule::PathGlob(pattern) => {
+                let options = glob::MatchOptions {
+                    require_literal_separator: true,
+                    ..Default::default()
+                };
+                glob::Pattern::new(pattern)
+                    .context(format!("Invalid path pattern: {}", pattern))?
+                    .matches_path_with(&track.path, options)
+            }
+            Rule::Modified { after, before } => {
+                let modified = fs::metadata(&track.path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .map(|time| DateTime::<Local>::from(time).date_naive());
+                modified.is_some_and(|date| {
+                    after.is_none_or(|after| date >= after) && before.is_none_or(|before| date <= before)
+                })
+            }
+        })
+    }
+}
+
+fn default_recursive() -> bool {
+    true
+}
+
+/// A rule-based playlist, evaluated against the files in a set of directories
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartPlaylist {
+    pub directories: Vec<PathBuf>,
+    /// Also look in subdirectories
+    #[serde(default = "default_recursive")]
+    pub recursive: bool,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub match_mode: RuleMatch,
+    #[serde(default)]
+    pub sort: Option<SortKey>,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// Maximum number of tracks, taken after sorting
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[allow(dead_code)]
+impl SmartPlaylist {
+    pub fn new(directories: Vec<PathBuf>) -> Self {
+        Self {
+            directories,
+            recursive: true,
+            rules: Vec::new(),
+            match_mode: RuleMatch::All,
+            sort: None,
+            order: SortOrder::Ascending,
+            limit: None,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .context(format!("Failed to read smart playlist: {}", path.display()))?;
+        serde_json::from_str(&content)
+            .context(format!("Failed to parse smart playlist: {}", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let content = serde_json::to_string_pretty(self)
+            .context("Failed to serialize smart playlist")?;
+
+        // Create parent directories if they don't exist
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .context("Failed to create smart playlist directory")?;
+        }
+
+        fs::write(path, content)
+            .context(format!("Failed to write smart playlist: {}", path.display()))
+    }
+
+    /// Whether a track satisfies the rules. A playlist without rules matches everything.
+    pub fn matches(&self, track: &AudioTrack) -> Result<bool> {
+        if self.rules.is_empty() {
+            return Ok(true);
+        }
+
+        for rule in &self.rules {
+            let matched = rule.matches(track)?;
+            match self.match_mode {
+                RuleMatch::All if !matched => return Ok(false),
+                RuleMatch::Any if matched => return Ok(true),
+                _ => {}
+            }
+        }
+        Ok(self.match_mode == RuleMatch::All)
+    }
+
+    /// Tracks of all matching audio files, sorted and limited as defined.
+    /// `stats` provides the play statistics for the statistic sort keys.
+    pub fn evaluate(&self, stats: &StatsStore) -> Result<Vec<AudioTrack>> {
+        let mut files = Vec::new();
+        for directory in &self.directories {
+            collect_files(directory, self.recursive, &mut files)
+                .context(format!("Failed to read directory: {}", directory.display()))?;
+        }
+        files.sort();
+        files.dedup();
+
+        // Rules on the file alone are checked before probing, when all rules must hold
+        let file_rules: Vec<&Rule> = self.rules.iter()
+            .filter(|rule| !rule.needs_metadata())
+            .collect();
+
+        let mut queue = AudioQueue::new();
+        for path in files {
+            if self.match_mode == RuleMatch::All {
+                let bare = AudioTrack { path: path.clone(), ..Default::default() };
+                let mut passes = true;
+                for rule in &file_rules {
+                    if !rule.matches(&bare)? {
+                        passes = false;
+                        break;
+                    }
+                }
+                if !passes {
+                    continue;
+                }
+            }
+
+            // An unreadable file shouldn't stop the rest of the library from being scanned
+            let probed = AudioQueue::validate_audio_file(&path).and_then(|valid| {
+                valid.then(|| AudioQueue::extract_metadata(&path)).transpose()
+            });
+            let track = match probed {
+                Ok(Some(track)) => track,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("Warning: Skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if self.matches(&track)? {
+                queue.add_track(track, None)?;
+            }
+        }
+
+        if let Some(key) = self.sort {
+            queue.sort_by_with_stats(key, self.order, stats);
+        }
+
+        let mut tracks: Vec<AudioTrack> = queue.get_queue().iter().cloned().collect();
+        if let Some(limit) = self.limit {
+            tracks.truncate(limit);
+        }
+
+        // IDs are assigned by the queue the tracks are added to
+        for track in &mut tracks {
+            track.id = 0;
+        }
+        Ok(tracks)
+    }
+}
+
+fn collect_files(directory: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        // Symlinked directories aren't followed, so a link up the tree can't loop
+        if entry.file_type()?.is_dir() {
+            if recursive {
+                collect_files(&path, recursive, files)?;
+            }
+        } else if !path.is_dir() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Smart playlist definitions saved as `<name>.json` files in one directory
+pub struct SmartPlaylistLibrary {
+    store: NamedStore,
+}
+
+#[allow(dead_code)]
+impl SmartPlaylistLibrary {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            store: NamedStore::new(directory, "Smart playlist"),
+        }
+    }
+
+    pub fn names(&self) -> Result<Vec<String>> {
+        self.store.names()
+    }
+
+    pub fn load(&self, name: &str) -> Result<SmartPlaylist> {
+        SmartPlaylist::load(self.store.existing_path_of(name)?)
+    }
+
+    pub fn save(&self, name: &str, playlist: &SmartPlaylist) -> Result<()> {
+        playlist.save(self.store.path_of(name)?)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.store.delete(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let track = AudioTrack {
+            path: PathBuf::from("/music/live/Set.FLAC"),
+            artist: Some("Daft Punk".to_string()),
+            duration: Some(300.0),
+            ..Default::default()
+        };
+
+        assert!(Rule::Artist("daft".to_string()).matches(&track).unwrap());
+        assert!(!Rule::Title("one".to_string()).matches(&track).unwrap());
+        assert!(Rule::Duration { min: Some(120.0), max: None }.matches(&track).unwrap());
+        assert!(!Rule::Duration { min: None, max: Some(120.0) }.matches(&track).unwrap());
+        assert!(Rule::Extension(vec![".flac".to_string()]).matches(&track).unwrap());
+        assert!(Rule::PathGlob("**/live/*.FLAC".to_string()).matches(&track).unwrap());
+        assert!(!Rule::PathGlob("/music/*.FLAC".to_string()).matches(&track).unwrap());
+        assert!(Rule::PathGlob("[".to_string()).matches(&track).is_err());
+
+        let mut playlist = SmartPlaylist::new(Vec::new());
+        playlist.rules = vec![Rule::Artist("daft".to_string()), Rule::Title("one".to_string())];
+        assert!(!playlist.matches(&track).unwrap());
+        playlist.match_mode = RuleMatch::Any;
+        assert!(playlist.matches(&track).unwrap());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::audio_queue::{canonical_path, AudioTrack};
+
+/// Usage data of one file, kept across sessions and queues
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TrackStats {
+    pub play_count: u32,
+    pub skip_count: u32,
+    pub last_played: Option<DateTime<Utc>>,
+    /// Total seconds listened
+    pub listened: f64,
+}
+
+/// Limits on play statistics used to select tracks. Unset limits match everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsFilter {
+    pub min_plays: Option<u32>,
+    pub max_plays: Option<u32>,
+    pub min_skips: Option<u32>,
+}
+
+impl StatsFilter {
+    pub fn matches(&self, stats: &TrackStats) -> bool {
+        self.min_plays.is_none_or(|min| stats.play_count >= min)
+            && self.max_plays.is_none_or(|max| stats.play_count <= max)
+            && self.min_skips.is_none_or(|min| stats.skip_count >= min)
+    }
+}
+
+/// Play statistics keyed by canonical file path
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct StatsStore {
+    pub tracks: BTreeMap<PathBuf, TrackStats>,
+}
+
+#[allow(dead_code)]
+impl StatsStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load statistics from file, starting empty if it doesn't exist yet
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+
+        let content = fs::read_to_string(path)
+            .context("Failed to read stats file")?;
+        serde_json::from_str(&content)
+            .context("Failed to parse stats file")
+    }
+
+    /// Save statistics to file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let content = serde_json::to_string_pretty(self)
+            .context("Failed to serialize stats")?;
+
+        // Create parent directories if they don't exist
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .context("Failed to create stats directory")?;
+        }
+
+        fs::write(path, content)
+            .context("Failed to write stats file")
+    }
+
+    /// Statistics of a file, or empty ones if it was never played
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> TrackStats {
+        self.tracks.get(&canonical_path(path.as_ref()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn entry(&mut self, path: &Path) -> &mut TrackStats {
+        self.tracks.entry(canonical_path(path)).or_default()
+    }
+
+    /// A track started playing
+    pub fn record_start<P: AsRef<Path>>(&mut self, path: P, at: DateTime<Utc>) {
+        let stats = self.entry(path.as_ref());
+        stats.play_count += 1;
+        stats.last_played = Some(at);
+    }
+
+    /// A track played to its end after `listened` seconds
+    pub fn record_finish<P: AsRef<Path>>(&mut self, path: P, listened: f64) {
+        self.entry(path.as_ref()).listened += listened.max(0.0);
+    }
+
+    /// A track was left for another one after `listened` seconds
+    pub fn record_skip<P: AsRef<Path>>(&mut self, path: P, listened: f64) {
+        let stats = self.entry(path.as_ref());
+        stats.skip_count += 1;
+        stats.listened += listened.max(0.0);
+    }
+
+    /// Positions of the tracks whose statistics pass `filter`
+    pub fn matching_positions<'a, I>(&self, tracks: I, filter: &StatsFilter) -> Vec<usize>
+    where
+        I: IntoIterator<Item = &'a AudioTrack>,
+    {
+        tracks.into_iter()
+            .enumerate()
+            .filter(|(_, track)| filter.matches(&self.get(&track.path)))
+            .map(|(position, _)| position)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_filter() {
+        let mut stats = StatsStore::new();
+        let now = Utc::now();
+        stats.record_start("a.mp3", now);
+        stats.record_finish("a.mp3", 120.0);
+        stats.record_start("a.mp3", now);
+        stats.record_skip("a.mp3", 30.0);
+        stats.record_start("b.mp3", now);
+
+        let a = stats.get("a.mp3");
+        assert_eq!(a.play_count, 2);
+        assert_eq!(a.skip_count, 1);
+        assert_eq!(a.listened, 150.0);
+        assert_eq!(a.last_played, Some(now));
+        assert_eq!(stats.get("never.mp3"), TrackStats::default());
+
+        let tracks: Vec<AudioTrack> = ["a.mp3", "b.mp3", "c.mp3"].iter()
+            .map(|path| AudioTrack { path: PathBuf::from(path), ..Default::default() })
+            .collect();
+        let filter = StatsFilter { min_plays: Some(1), ..Default::default() };
+        assert_eq!(stats.matching_positions(&tracks, &filter), vec![0, 1]);
+        let filter = StatsFilter { max_plays: Some(0), ..Default::default() };
+        assert_eq!(stats.matching_positions(&tracks, &filter), vec![2]);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        let mut stats = StatsStore::new();
+        stats.record_start("a.mp3", Utc::now());
+        stats.save(&path).unwrap();
+
+        let loaded = StatsStore::load(&path).unwrap();
+        assert_eq!(loaded, stats);
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow, Context};
+use serde::{Serialize, Deserialize};
+
+use crate::audio_queue::{canonical_path, AudioTrack};
+
+/// Highest star rating a track can have
+pub const MAX_RATING: u8 = 5;
+
+/// Rating and labels the user gave to a file
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TrackTags {
+    /// Stars from 1 to `MAX_RATING`, None if unrated
+    pub rating: Option<u8>,
+    pub labels: BTreeSet<String>,
+}
+
+impl TrackTags {
+    pub fn is_empty(&self) -> bool {
+        self.rating.is_none() && self.labels.is_empty()
+    }
+}
+
+/// Normalized form of a label: trimmed and lowercase
+pub fn normalize_label(label: &str) -> Result<String> {
+    let label = label.trim().to_lowercase();
+    if label.is_empty() {
+        return Err(anyhow!("Labels cannot be empty"));
+    }
+    Ok(label)
+}
+
+/// Selects tracks by rating and labels. An empty filter matches every track.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagFilter {
+    pub min_rating: Option<u8>,
+    /// Labels a track must all carry
+    pub labels: Vec<String>,
+}
+
+impl TagFilter {
+    pub fn matches(&self, track: &AudioTrack) -> bool {
+        self.min_rating.is_none_or(|min| track.rating.is_some_and(|rating| rating >= min))
+            && self.labels.iter().all(|label| track.labels.contains(&label.to_lowercase()))
+    }
+}
+
+/// Ratings and labels keyed by canonical file path, so they survive re-adding a file
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TagStore {
+    pub tracks: BTreeMap<PathBuf, TrackTags>,
+}
+
+#[allow(dead_code)]
+impl TagStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load tags from file, starting empty if it doesn't exist yet
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+
+        let content = fs::read_to_string(path)
+            .context("Failed to read tags file")?;
+        serde_json::from_str(&content)
+            .context("Failed to parse tags file")
+    }
+
+    /// Save tags to file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let content = serde_json::to_string_pretty(self)
+            .context("Failed to serialize tags")?;
+
+        // Create parent directories if they don't exist
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .context("Failed to create tags directory")?;
+        }
+
+        fs::write(path, content)
+            .context("Failed to write tags file")
+    }
+
+    /// Tags of a file, or empty ones if it has none
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> TrackTags {
+        self.tracks.get(&canonical_path(path.as_ref()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Set the rating of a file, or clear it with None
+    pub fn set_rating<P: AsRef<Path>>(&mut self, path: P, rating: Option<u8>) -> Result<TrackTags> {
+        if let Some(rating) = rating {
+            if !(1..=MAX_RATING).contains(&rating) {
+                return Err(anyhow!("Rating must be between 1 and {}", MAX_RATING));
+            }
+        }
+
+        self.update(path.as_ref(), |tags| tags.rating = rating)
+    }
+
+    pub fn add_labels<P: AsRef<Path>>(&mut self, path: P, labels: &[String]) -> Result<TrackTags> {
+        let labels = labels.iter()
+            .map(|label| normalize_label(label))
+            .collect::<Result<Vec<_>>>()?;
+        self.update(path.as_ref(), |tags| tags.labels.extend(labels))
+    }
+
+    pub fn remove_labels<P: AsRef<Path>>(&mut self, path: P, labels: &[String]) -> Result<TrackTags> {
+        let labels = labels.iter()
+            .map(|label| normalize_label(label))
+            .collect::<Result<Vec<_>>>()?;
+        self.update(path.as_ref(), |tags| tags.labels.retain(|label| !labels.contains(label)))
+    }
+
+    pub fn clear_labels<P: AsRef<Path>>(&mut self, path: P) -> Result<TrackTags> {
+        self.update(path.as_ref(), |tags| tags.labels.clear())
+    }
+
+    /// Copy the stored rating and labels of the track's file onto the track
+    pub fn apply(&self, track: &mut AudioTrack) {
+        let tags = self.get(&track.path);
+        track.rating = tags.rating;
+        track.labels = tags.labels;
+    }
+
+    fn update(&mut self, path: &Path, change: impl FnOnce(&mut TrackTags)) -> Result<TrackTags> {
+        let key = canonical_path(path);
+        let mut tags = self.tracks.remove(&key).unwrap_or_default();
+        change(&mut tags);
+
+        // Files without tags are dropped so the store only holds curated files
+        if !tags.is_empty() {
+            self.tracks.insert(key, tags.clone());
+        }
+        Ok(tags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rating_and_labels() {
+        let mut store = TagStore::new();
+        assert!(store.set_rating("a.mp3", Some(6)).is_err());
+        store.set_rating("a.mp3", Some(4)).unwrap();
+        store.add_labels("a.mp3", &["Chill".to_string(), " intro ".to_string()]).unwrap();
+        assert!(store.add_labels("a.mp3", &[" ".to_string()]).is_err());
+
+        let tags = store.get("a.mp3");
+        assert_eq!(tags.rating, Some(4));
+        assert_eq!(tags.labels.iter().collect::<Vec<_>>(), ["chill", "intro"]);
+
+        let mut track = AudioTrack { path: PathBuf::from("a.mp3"), ..Default::default() };
+        store.apply(&mut track);
+        assert!(TagFilter { min_rating: Some(3), labels: vec!["CHILL".to_string()] }.matches(&track));
+        assert!(!TagFilter { min_rating: Some(5), labels: Vec::new() }.matches(&track));
+
+        store.set_rating("a.mp3", None).unwrap();
+        store.clear_labels("a.mp3").unwrap();
+        assert!(store.tracks.is_empty());
+    }
+}
@@ -1,5 +1,14 @@
 use std::path::PathBuf;
-use audioqueue::audio_queue::{AudioQueue, AudioTrack, PlaybackState};
+use chrono::{Duration, Utc};
+use audioqueue::track_stats::StatsStore;
+use audioqueue::track_tags::{TagFilter, TagStore};
+use audioqueue::playlist::PlaylistFormat;
+use audioqueue::queue_snapshot::SnapshotLibrary;
+use audioqueue::smart_playlist::{Rule, SmartPlaylist, SmartPlaylistLibrary};
+use audioqueue::audio_queue::{
+    parse_timestamp, AddOutcome, AudioQueue, AudioTrack, DuplicatePolicy, MatchMode, PlaybackState, RepeatMode,
+    SearchField, MAX_HISTORY_ENTRIES, SortKey, SortOrder, MAX_JOURNAL_DEPTH,
+};
 use anyhow::Result;
 
 #[cfg(test)]
@@ -13,9 +22,31 @@ mod tests {
             artist: artist.map(|s| s.to_string()),
             duration: Some(120.0),
             position: 0,
+            ..Default::default()
         }
     }
 
+    /// Write a silent 8 kHz mono WAV file lasting `seconds`
+    fn write_wav(path: &std::path::Path, seconds: u32) {
+        let sample_rate: u32 = 8000;
+        let data_len = sample_rate * seconds * 2;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        bytes.resize(bytes.len() + data_len as usize, 0);
+        std::fs::write(path, bytes).unwrap();
+    }
+
     #[test]
     fn test_audio_queue_creation() {
         let queue = AudioQueue::new();
@@ -326,6 +357,1196 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_shuffle_keeps_curated_order() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=5 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+
+        queue.play()?;
+        queue.set_shuffle(true);
+        assert!(queue.is_shuffle_enabled());
+
+        // The play order is a permutation that starts with the current track
+        let mut order = queue.play_order();
+        assert_eq!(order[0], 0);
+        order.sort();
+        assert_eq!(order, vec![0, 1, 2, 3, 4]);
+
+        // Walking the shuffled order visits every track exactly once
+        let mut visited = vec![queue.current_position.unwrap()];
+        while queue.next_track().is_ok() {
+            visited.push(queue.current_position.unwrap());
+        }
+        visited.sort();
+        assert_eq!(visited, vec![0, 1, 2, 3, 4]);
+
+        // The curated order is untouched, so turning shuffle off restores it
+        for (index, track) in queue.get_queue().iter().enumerate() {
+            assert_eq!(track.path, PathBuf::from(format!("test{}.mp3", index + 1)));
+        }
+        queue.set_shuffle(false);
+        assert_eq!(queue.play_order(), vec![0, 1, 2, 3, 4]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shuffle_order_follows_mutations() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=4 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+
+        queue.set_shuffle(true);
+        queue.add_track(create_test_track("test5.mp3", None, None), Some(1))?;
+        queue.remove_track(3)?;
+        queue.move_track(0, 3)?;
+
+        let mut order = queue.play_order();
+        order.sort();
+        assert_eq!(order, vec![0, 1, 2, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shuffle_state_round_trip() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=4 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+        queue.set_shuffle(true);
+
+        let restored = AudioQueue::from_state(queue.to_state());
+        assert!(restored.is_shuffle_enabled());
+        assert_eq!(restored.play_order(), queue.play_order());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeat_modes() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=3 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+
+        // Off: stop at the end of the queue
+        queue.jump_to(2)?;
+        assert!(queue.next_track().is_err());
+        assert!(queue.advance_after_finish().is_err());
+
+        // All: wrap around in both directions
+        queue.set_repeat_mode(RepeatMode::All);
+        queue.next_track()?;
+        assert_eq!(queue.current_position, Some(0));
+        queue.previous()?;
+        assert_eq!(queue.current_position, Some(2));
+        queue.advance_after_finish()?;
+        assert_eq!(queue.current_position, Some(0));
+
+        // One: auto-advance replays the current track, manual next still moves
+        queue.set_repeat_mode(RepeatMode::One);
+        queue.advance_after_finish()?;
+        assert_eq!(queue.current_position, Some(0));
+        queue.next_track()?;
+        assert_eq!(queue.current_position, Some(1));
+
+        let restored = AudioQueue::from_state(queue.to_state());
+        assert_eq!(restored.repeat_mode(), RepeatMode::One);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_track_ids_are_stable() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=4 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+
+        let ids: Vec<u64> = queue.get_queue().iter().map(|t| t.id).collect();
+        assert!(ids.iter().all(|&id| id != 0));
+        assert_eq!(ids.iter().collect::<std::collections::HashSet<_>>().len(), 4);
+
+        // Removing by ID targets the same track no matter how positions shift
+        let target = ids[2];
+        queue.remove_track(0)?;
+        queue.remove_track_by_id(target)?;
+        assert!(queue.get_track_by_id(target).is_none());
+        assert_eq!(queue.get_queue().len(), 2);
+
+        queue.move_track_by_id(ids[3], 0)?;
+        assert_eq!(queue.get_queue()[0].id, ids[3]);
+
+        queue.jump_to_id(ids[1])?;
+        assert_eq!(queue.get_current_track().unwrap().id, ids[1]);
+
+        assert!(queue.remove_track_by_id(999).is_err());
+
+        // IDs survive a state round trip and new tracks never reuse them
+        let mut restored = AudioQueue::from_state(queue.to_state());
+        assert_eq!(restored.position_of(ids[3]), Some(0));
+        restored.add_track(create_test_track("test5.mp3", None, None), None)?;
+        assert!(restored.get_queue()[2].id > ids[3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_drives_previous() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=4 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+
+        // Play 1, jump to 4, then advance to 2 by jumping
+        queue.play()?;
+        queue.jump_to(3)?;
+        queue.jump_to(1)?;
+        assert_eq!(queue.get_history().len(), 3);
+
+        // Previous returns to the last track heard, not index minus one
+        queue.previous()?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("test4.mp3"));
+        queue.previous()?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("test1.mp3"));
+
+        // History entries of removed tracks are skipped
+        queue.jump_to(2)?;
+        queue.jump_to(1)?;
+        queue.remove_track(2)?;
+        queue.previous()?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("test1.mp3"));
+
+        let restored = AudioQueue::from_state(queue.to_state());
+        assert_eq!(restored.get_history(), queue.get_history());
+        assert!(queue.display_history().contains("Playback History"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_is_capped() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=2 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+
+        queue.play()?;
+        for i in 0..(MAX_HISTORY_ENTRIES + 10) {
+            queue.jump_to(i % 2)?;
+        }
+        assert_eq!(queue.get_history().len(), MAX_HISTORY_ENTRIES);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_redo_queue_changes() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=3 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+        queue.jump_to(1)?;
+
+        let paths = |queue: &AudioQueue| -> Vec<PathBuf> {
+            queue.get_queue().iter().map(|t| t.path.clone()).collect()
+        };
+        let original = paths(&queue);
+        let original_ids: Vec<u64> = queue.get_queue().iter().map(|t| t.id).collect();
+
+        queue.move_track(0, 2)?;
+        queue.remove_track(0)?;
+        queue.clear()?;
+        assert!(queue.get_queue().is_empty());
+
+        queue.undo()?;
+        assert_eq!(queue.get_queue().len(), 2);
+        queue.undo()?;
+        queue.undo()?;
+        assert_eq!(paths(&queue), original);
+        assert_eq!(queue.get_queue().iter().map(|t| t.id).collect::<Vec<_>>(), original_ids);
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("test2.mp3"));
+
+        // Redo replays the changes in order
+        queue.redo()?;
+        queue.redo()?;
+        queue.redo()?;
+        assert!(queue.get_queue().is_empty());
+        assert!(queue.redo().is_err());
+
+        // Undoing the adds themselves empties the queue
+        queue.undo()?;
+        queue.undo()?;
+        queue.undo()?;
+        queue.undo()?;
+        queue.undo()?;
+        queue.undo()?;
+        assert!(queue.get_queue().is_empty());
+        assert!(queue.undo().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_depth_and_new_change_drops_redo() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 0..(MAX_JOURNAL_DEPTH + 5) {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+
+        let mut undone = 0;
+        while queue.undo().is_ok() {
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_JOURNAL_DEPTH);
+        assert_eq!(queue.get_queue().len(), 5);
+
+        queue.remove_track(0)?;
+        assert!(!queue.get_journal().can_redo());
+
+        // The journal is part of the persisted state
+        let mut restored = AudioQueue::from_state(queue.to_state());
+        restored.undo()?;
+        assert_eq!(restored.get_queue().len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_up_next_lane() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=3 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+        queue.play()?;
+
+        queue.add_next(create_test_track("next1.mp3", None, None));
+        queue.add_next(create_test_track("next2.mp3", None, None));
+        assert_eq!(queue.get_up_next().len(), 2);
+        assert_eq!(queue.get_queue().len(), 3);
+
+        let display = queue.display_queue();
+        assert!(display.contains("Up Next"));
+        assert!(display.contains("next1"));
+
+        // Lane tracks play first-in-first-out before the rest of the queue
+        let restored = AudioQueue::from_state(queue.to_state());
+        assert_eq!(restored.get_up_next().len(), 2);
+
+        queue.next_track()?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("next1.mp3"));
+        queue.next_track()?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("next2.mp3"));
+        queue.next_track()?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("test2.mp3"));
+        assert!(queue.get_up_next().is_empty());
+
+        // Played lane tracks stay in the queue after the track they followed
+        assert_eq!(queue.get_queue()[1].path, PathBuf::from("next1.mp3"));
+        assert_eq!(queue.get_queue()[2].path, PathBuf::from("next2.mp3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_up_next_with_shuffle() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=5 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+        queue.play()?;
+        queue.set_shuffle(true);
+
+        queue.add_next(create_test_track("next.mp3", None, None));
+        queue.next_track()?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("next.mp3"));
+
+        let mut order = queue.play_order();
+        order.sort();
+        assert_eq!(order, (0..6).collect::<Vec<_>>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_tracks() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        queue.add_track(create_test_track("music/intro.mp3", Some("Morning Intro"), Some("DJ One")), None)?;
+        queue.add_track(create_test_track("music/song.flac", Some("Evening Song"), Some("Band Two")), None)?;
+        queue.add_track(create_test_track("jingles/id.wav", Some("Station ID"), Some("DJ One")), None)?;
+
+        assert_eq!(queue.find("dj one", SearchField::Artist, MatchMode::Substring)?, vec![0, 2]);
+        assert_eq!(queue.find("song", SearchField::Title, MatchMode::Substring)?, vec![1]);
+        assert_eq!(queue.find("jingles/", SearchField::Path, MatchMode::Substring)?, vec![2]);
+        assert_eq!(queue.find("intro", SearchField::Any, MatchMode::Substring)?, vec![0]);
+        assert_eq!(queue.find(r"\.(mp3|wav)$", SearchField::Path, MatchMode::Regex)?, vec![0, 2]);
+        assert_eq!(queue.find("^(morning|evening)", SearchField::Title, MatchMode::Regex)?, vec![0, 1]);
+        assert!(queue.find("nothing", SearchField::Any, MatchMode::Substring)?.is_empty());
+        assert!(queue.find("(unclosed", SearchField::Any, MatchMode::Regex).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_promote_matches_to_up_next() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=5 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+        queue.play()?;
+
+        queue.promote_to_up_next(&[4, 0, 2])?;
+        assert_eq!(queue.get_queue().len(), 3);
+        let lane: Vec<PathBuf> = queue.get_up_next().iter().map(|t| t.path.clone()).collect();
+        assert_eq!(lane, vec![PathBuf::from("test3.mp3"), PathBuf::from("test5.mp3")]);
+
+        queue.next_track()?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("test3.mp3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_restores_up_next_lane() -> Result<()> {
+        let mut queue = AudioQueue::new();
+
+        for i in 1..=3 {
+            let track = create_test_track(&format!("test{}.mp3", i), None, None);
+            queue.add_track(track, None)?;
+        }
+        queue.play()?;
+
+        let lane = |queue: &AudioQueue| -> Vec<PathBuf> {
+            queue.get_up_next().iter().map(|t| t.path.clone()).collect()
+        };
+
+        queue.add_next(create_test_track("next.mp3", None, None));
+        queue.undo()?;
+        assert!(queue.get_up_next().is_empty());
+        queue.redo()?;
+        assert_eq!(lane(&queue), vec![PathBuf::from("next.mp3")]);
+
+        // Taking a lane track puts it back in the lane, and the old track is current again
+        queue.next_track()?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("next.mp3"));
+        queue.undo()?;
+        assert_eq!(lane(&queue), vec![PathBuf::from("next.mp3")]);
+        assert_eq!(queue.get_queue().len(), 3);
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("test1.mp3"));
+        queue.redo()?;
+        assert!(queue.get_up_next().is_empty());
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("next.mp3"));
+        queue.undo()?;
+
+        queue.promote_to_up_next(&[2, 1])?;
+        assert_eq!(lane(&queue), vec![
+            PathBuf::from("next.mp3"), PathBuf::from("test2.mp3"), PathBuf::from("test3.mp3"),
+        ]);
+        queue.undo()?;
+        assert_eq!(lane(&queue), vec![PathBuf::from("next.mp3")]);
+        let paths: Vec<PathBuf> = queue.get_queue().iter().map(|t| t.path.clone()).collect();
+        assert_eq!(paths, vec![PathBuf::from("test1.mp3"), PathBuf::from("test2.mp3"), PathBuf::from("test3.mp3")]);
+        queue.redo()?;
+        assert_eq!(queue.get_queue().len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_keeps_current_track() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        queue.add_track(create_test_track("c.mp3", Some("Charlie"), Some("Zed")), None)?;
+        queue.add_track(create_test_track("a.mp3", Some("Alpha"), Some("Yan")), None)?;
+        queue.add_track(create_test_track("b.mp3", Some("Bravo"), Some("Yan")), None)?;
+        queue.add_track(create_test_track("d.mp3", None, Some("Xia")), None)?;
+        queue.jump_to(2)?;
+
+        let titles = |queue: &AudioQueue| -> Vec<Option<String>> {
+            queue.get_queue().iter().map(|t| t.title.clone()).collect()
+        };
+
+        queue.sort_by(SortKey::Title, SortOrder::Ascending);
+        assert_eq!(titles(&queue), vec![
+            Some("Alpha".to_string()), Some("Bravo".to_string()), Some("Charlie".to_string()), None,
+        ]);
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("b.mp3"));
+
+        queue.sort_by(SortKey::Title, SortOrder::Descending);
+        assert_eq!(queue.get_queue()[0].path, PathBuf::from("c.mp3"));
+        assert_eq!(queue.get_queue()[3].path, PathBuf::from("d.mp3"));
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("b.mp3"));
+
+        // Ties on artist are broken by title
+        queue.sort_by(SortKey::Artist, SortOrder::Ascending);
+        let paths: Vec<PathBuf> = queue.get_queue().iter().map(|t| t.path.clone()).collect();
+        assert_eq!(paths, ["d.mp3", "a.mp3", "b.mp3", "c.mp3"].map(PathBuf::from));
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("b.mp3"));
+
+        // Sorting is undoable
+        queue.undo()?;
+        assert_eq!(queue.get_queue()[0].path, PathBuf::from("c.mp3"));
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("b.mp3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_policy() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        queue.add_track(create_test_track("a.mp3", Some("A"), None), None)?;
+        queue.add_track(create_test_track("b.mp3", Some("B"), None), None)?;
+        queue.add_track(create_test_track("c.mp3", Some("C"), None), None)?;
+
+        // Duplicates are allowed by default
+        assert_eq!(queue.add_track(create_test_track("a.mp3", None, None), None)?, AddOutcome::Added(3));
+        queue.undo()?;
+
+        queue.set_duplicate_policy(DuplicatePolicy::Skip, false);
+        assert_eq!(queue.add_track(create_test_track("a.mp3", None, None), None)?, AddOutcome::Skipped(0));
+        assert_eq!(queue.get_queue().len(), 3);
+
+        queue.jump_to(0)?;
+        queue.set_duplicate_policy(DuplicatePolicy::MoveExisting, false);
+        assert_eq!(
+            queue.add_track(create_test_track("a.mp3", None, None), None)?,
+            AddOutcome::Moved { from: 0, to: 2 }
+        );
+        let paths: Vec<PathBuf> = queue.get_queue().iter().map(|t| t.path.clone()).collect();
+        assert_eq!(paths, ["b.mp3", "c.mp3", "a.mp3"].map(PathBuf::from));
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("a.mp3"));
+
+        // Edited tracks are compared as they are now, not as they were added
+        queue.set_duplicate_policy(DuplicatePolicy::Skip, false);
+        queue.set_trim(0, Some(10.0), None)?;
+        assert_eq!(queue.add_track(create_test_track("b.mp3", None, None), None)?, AddOutcome::Added(3));
+        queue.set_trim(3, Some(10.0), None)?;
+        assert_eq!(queue.add_track(create_test_track("c.mp3", None, None), None)?, AddOutcome::Skipped(1));
+        assert_eq!(queue.duplicate_positions(), vec![3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedupe_keeps_current_track() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for path in ["a.mp3", "b.mp3", "a.mp3", "c.mp3", "b.mp3"] {
+            queue.add_track(create_test_track(path, None, None), None)?;
+        }
+        queue.jump_to(4)?;
+
+        assert_eq!(queue.duplicate_positions(), vec![1, 2]);
+        let removed = queue.dedupe();
+        assert_eq!(removed.len(), 2);
+
+        let paths: Vec<PathBuf> = queue.get_queue().iter().map(|t| t.path.clone()).collect();
+        assert_eq!(paths, ["a.mp3", "c.mp3", "b.mp3"].map(PathBuf::from));
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("b.mp3"));
+        assert!(queue.dedupe().is_empty());
+
+        queue.undo()?;
+        assert_eq!(queue.get_queue().len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_content_matching() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        std::fs::write(dir.join("one.mp3"), b"same bytes")?;
+        std::fs::write(dir.join("two.mp3"), b"same bytes")?;
+        std::fs::write(dir.join("three.mp3"), b"other byte")?;
+
+        let mut queue = AudioQueue::new();
+        for name in ["one.mp3", "two.mp3", "three.mp3"] {
+            let track = create_test_track(dir.join(name).to_str().unwrap(), None, None);
+            queue.add_track(track, None)?;
+        }
+
+        // Different paths are only duplicates when content matching is on
+        assert!(queue.duplicate_positions().is_empty());
+        queue.set_duplicate_policy(DuplicatePolicy::Skip, true);
+        assert_eq!(queue.duplicate_positions(), vec![1]);
+
+        // Paths are compared after canonicalization
+        let dotted = dir.join(".").join("three.mp3");
+        let outcome = queue.add_track(create_test_track(dotted.to_str().unwrap(), None, None), None)?;
+        assert_eq!(outcome, AddOutcome::Skipped(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_operations_keep_current_track() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for i in 0..8 {
+            queue.add_track(create_test_track(&format!("t{}.mp3", i), None, None), None)?;
+        }
+        let paths = |queue: &AudioQueue| -> Vec<String> {
+            queue.get_queue().iter().map(|t| t.path.to_string_lossy().to_string()).collect()
+        };
+
+        queue.jump_to(5)?;
+        let removed = queue.remove_range(1..3)?;
+        assert_eq!(removed.len(), 2);
+        assert_eq!(paths(&queue), ["t0.mp3", "t3.mp3", "t4.mp3", "t5.mp3", "t6.mp3", "t7.mp3"]);
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("t5.mp3"));
+
+        // Moving a block past the current track
+        queue.move_block(0..2, 3)?;
+        assert_eq!(paths(&queue), ["t4.mp3", "t5.mp3", "t6.mp3", "t0.mp3", "t3.mp3", "t7.mp3"]);
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("t5.mp3"));
+        assert!(queue.move_block(0..2, 5).is_err());
+
+        // Removing the current track selects the next remaining one
+        queue.remove_range(1..3)?;
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("t0.mp3"));
+
+        queue.keep_range(1..3)?;
+        assert_eq!(paths(&queue), ["t0.mp3", "t3.mp3"]);
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("t0.mp3"));
+        assert!(queue.keep_range(1..3).is_err());
+
+        // Each bulk change is a single undo step
+        queue.undo()?;
+        queue.undo()?;
+        assert_eq!(paths(&queue), ["t4.mp3", "t5.mp3", "t6.mp3", "t0.mp3", "t3.mp3", "t7.mp3"]);
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("t5.mp3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_queue_timing() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for i in 1..=3 {
+            queue.add_track(create_test_track(&format!("test{}.mp3", i), None, None), None)?;
+        }
+        let mut unknown = create_test_track("unknown.mp3", None, None);
+        unknown.duration = None;
+        queue.add_track(unknown, None)?;
+        queue.add_track(create_test_track("last.mp3", None, None), None)?;
+
+        let now = Utc::now();
+        let timing = queue.timing(now);
+        assert_eq!(timing.total, 480.0);
+        assert_eq!(timing.unknown_durations, 1);
+        assert_eq!(timing.upcoming.len(), 5);
+
+        queue.play()?;
+        queue.pause()?;
+        let timing = queue.timing(now);
+        assert!(timing.elapsed < 1.0);
+        let remaining = 480.0 - timing.elapsed;
+        assert!((timing.remaining - remaining).abs() < 1e-6);
+
+        // Start estimates follow the play order and stop after an unknown duration
+        let starts: Vec<_> = timing.upcoming.iter().map(|eta| eta.starts_at).collect();
+        assert_eq!(timing.upcoming[0].position, Some(1));
+        let first_start = starts[0].unwrap();
+        assert_eq!(starts[1], Some(first_start + Duration::seconds(120)));
+        assert_eq!(starts[2], Some(first_start + Duration::seconds(240)));
+        assert_eq!(starts[3], None);
+
+        // The up-next lane plays before the rest of the queue
+        queue.add_next(create_test_track("next.mp3", None, None));
+        let timing = queue.timing(now);
+        assert_eq!(timing.upcoming[0].position, None);
+        assert_eq!(timing.total, 600.0);
+
+        assert_eq!(audioqueue::audio_queue::format_duration(3725.0), "1:02:05");
+        assert_eq!(audioqueue::audio_queue::format_duration(65.0), "1:05");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_by_play_statistics() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for path in ["a.mp3", "b.mp3", "c.mp3"] {
+            queue.add_track(create_test_track(path, Some(path), None), None)?;
+        }
+
+        let mut stats = StatsStore::new();
+        let now = Utc::now();
+        for _ in 0..3 {
+            stats.record_start("b.mp3", now);
+        }
+        stats.record_start("c.mp3", now - Duration::hours(1));
+
+        queue.sort_by_with_stats(SortKey::Plays, SortOrder::Descending, &stats);
+        let paths: Vec<PathBuf> = queue.get_queue().iter().map(|t| t.path.clone()).collect();
+        assert_eq!(paths, ["b.mp3", "c.mp3", "a.mp3"].map(PathBuf::from));
+
+        // Tracks that were never played go last
+        queue.sort_by_with_stats(SortKey::LastPlayed, SortOrder::Ascending, &stats);
+        let paths: Vec<PathBuf> = queue.get_queue().iter().map(|t| t.path.clone()).collect();
+        assert_eq!(paths, ["c.mp3", "b.mp3", "a.mp3"].map(PathBuf::from));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tags_filter_sort_and_export() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for path in ["a.mp3", "b.mp3", "c.mp3", "b.mp3"] {
+            queue.add_track(create_test_track(path, Some(path), Some("Artist")), None)?;
+        }
+
+        // Tags are kept per file, so every queued copy gets them
+        let mut store = TagStore::new();
+        let tags = store.set_rating("b.mp3", Some(5))?;
+        assert_eq!(queue.set_tags(&PathBuf::from("b.mp3"), &tags), 2);
+        let tags = store.add_labels("b.mp3", &["chill".to_string()])?;
+        queue.set_tags(&PathBuf::from("b.mp3"), &tags);
+        let tags = store.set_rating("c.mp3", Some(3))?;
+        queue.set_tags(&PathBuf::from("c.mp3"), &tags);
+
+        let mut readded = create_test_track("c.mp3", None, None);
+        store.apply(&mut readded);
+        assert_eq!(readded.rating, Some(3));
+
+        let filter = TagFilter { min_rating: Some(4), labels: vec!["chill".to_string()] };
+        assert_eq!(queue.filter_by_tags(&filter), vec![1, 3]);
+        assert!(queue.display_queue().contains("★★★★★ #chill"));
+
+        queue.sort_by(SortKey::Rating, SortOrder::Descending);
+        let paths: Vec<PathBuf> = queue.get_queue().iter().map(|t| t.path.clone()).collect();
+        assert_eq!(paths, ["b.mp3", "b.mp3", "c.mp3", "a.mp3"].map(PathBuf::from));
+
+        let temp = tempfile::tempdir()?;
+        let playlist = temp.path().join("export.m3u");
+        queue.save_playlist_matching(&playlist, &TagFilter { min_rating: Some(3), labels: Vec::new() })?;
+        let content = std::fs::read_to_string(&playlist)?;
+        assert_eq!(content.lines().filter(|line| !line.starts_with('#')).count(), 3);
+        assert!(!content.contains("a.mp3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim_points() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        queue.add_track(create_test_track("show.mp3", None, None), None)?;
+        queue.add_track(create_test_track("song.mp3", None, None), None)?;
+
+        // Only the section between the offsets counts towards the queue length
+        let trimmed = queue.set_trim(0, Some(30.0), Some(90.0))?;
+        assert_eq!(trimmed.playable_duration(), Some(60.0));
+        assert_eq!(queue.timing(Utc::now()).total, 180.0);
+        assert!(queue.display_queue().contains("(60.0s) [0:30-1:30]"));
+
+        // An end past the end of the file plays to the end
+        queue.set_trim(1, Some(100.0), Some(500.0))?;
+        assert_eq!(queue.get_queue()[1].playable_duration(), Some(20.0));
+
+        assert!(queue.set_trim(0, Some(90.0), Some(30.0)).is_err());
+        assert!(queue.set_trim(0, Some(120.0), None).is_err());
+        assert!(queue.set_trim(0, Some(-1.0), None).is_err());
+
+        // Offsets survive the state file and trimming can be undone
+        let json = serde_json::to_string(&queue.to_state())?;
+        let restored = AudioQueue::from_state(serde_json::from_str(&json)?);
+        assert_eq!(restored.get_queue()[0].start, Some(30.0));
+        assert_eq!(restored.get_queue()[0].end, Some(90.0));
+
+        queue.undo()?;
+        assert_eq!(queue.get_queue()[1].end, None);
+
+        assert_eq!(parse_timestamp("90")?, 90.0);
+        assert_eq!(parse_timestamp("1:30")?, 90.0);
+        assert_eq!(parse_timestamp("1:02:03.5")?, 3723.5);
+        assert!(parse_timestamp("1:75").is_err());
+        assert!(parse_timestamp("abc").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_track_options_playlist_round_trip() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let file = dir.join("show.wav");
+        write_wav(&file, 10);
+
+        let mut queue = AudioQueue::new();
+        queue.add_track(AudioQueue::extract_metadata(&file)?, None)?;
+        queue.add_track(AudioQueue::extract_metadata(&file)?, None)?;
+        queue.set_trim(0, Some(2.5), Some(7.0))?;
+        queue.set_gain(1, 3.5)?;
+
+        let playlist = dir.join("trimmed.m3u");
+        queue.save_playlist(&playlist)?;
+        let content = std::fs::read_to_string(&playlist)?;
+        assert!(content.contains("#EXTVLCOPT:start-time=2.5"));
+        assert!(content.contains("#EXTVLCOPT:stop-time=7"));
+
+        // Options only apply to the entry that follows them
+        let mut loaded = AudioQueue::new();
+        loaded.load_playlist(&playlist, &TagStore::new())?;
+        assert_eq!(loaded.get_queue()[0].start, Some(2.5));
+        assert_eq!(loaded.get_queue()[0].end, Some(7.0));
+        assert_eq!(loaded.get_queue()[1].start, None);
+        assert_eq!(loaded.get_queue()[0].playable_duration(), Some(4.5));
+        assert_eq!(loaded.get_queue()[0].gain_db, 0.0);
+        assert_eq!(loaded.get_queue()[1].gain_db, 3.5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_track_gain() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        queue.add_track(create_test_track("hot.mp3", None, None), None)?;
+
+        let track = queue.set_gain(0, -6.0)?;
+        assert!((track.gain_factor() - 0.501).abs() < 0.001);
+        assert!(queue.display_queue().contains("-6.0 dB"));
+        assert!(queue.set_gain(0, 40.0).is_err());
+        assert!(queue.set_gain(1, 0.0).is_err());
+
+        let json = serde_json::to_string(&queue.to_state())?;
+        let restored = AudioQueue::from_state(serde_json::from_str(&json)?);
+        assert_eq!(restored.get_queue()[0].gain_db, -6.0);
+
+        queue.undo()?;
+        assert_eq!(queue.get_queue()[0].gain_db, 0.0);
+        assert_eq!(queue.get_queue()[0].gain_factor(), 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stop_after_marker() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for i in 1..=3 {
+            queue.add_track(create_test_track(&format!("test{}.mp3", i), None, None), None)?;
+        }
+        queue.play()?;
+
+        // Without a marker a finished track advances as usual
+        assert!(!queue.stop_at_marker());
+        queue.advance_after_finish()?;
+        assert_eq!(queue.current_position, Some(1));
+
+        let marked = queue.get_queue()[1].id;
+        assert!(queue.set_stop_after(Some(999)).is_err());
+        queue.set_stop_after(Some(marked))?;
+        assert!(queue.display_queue().contains("⏹ stop after"));
+
+        // The marker survives the state file
+        let json = serde_json::to_string(&queue.to_state())?;
+        let restored = AudioQueue::from_state(serde_json::from_str(&json)?);
+        assert_eq!(restored.stop_after(), Some(marked));
+
+        // It follows the track when it moves, then fires once
+        queue.move_track(1, 0)?;
+        assert_eq!(queue.current_position, Some(0));
+        assert!(queue.stop_at_marker());
+        assert_eq!(queue.playback_state, PlaybackState::Stopped);
+        assert_eq!(queue.current_position, Some(1));
+        assert_eq!(queue.stop_after(), None);
+
+        queue.play()?;
+        assert!(!queue.stop_at_marker());
+
+        // Removing the marked track removes the marker too
+        queue.set_stop_after(Some(marked))?;
+        queue.remove_track_by_id(marked)?;
+        assert_eq!(queue.stop_after(), None);
+        queue.set_stop_after(Some(queue.get_queue()[0].id))?;
+        queue.remove_positions(&[0])?;
+        assert_eq!(queue.stop_after(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_consume_mode() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        for i in 1..=4 {
+            queue.add_track(create_test_track(&format!("test{}.mp3", i), None, None), None)?;
+        }
+        queue.set_consume(true);
+
+        // Browsing a stopped queue removes nothing
+        queue.next_track()?;
+        assert_eq!(queue.get_queue().len(), 4);
+        queue.jump_to(0)?;
+
+        // Finished and skipped tracks are removed, the new track stays current
+        queue.play()?;
+        queue.advance_after_finish()?;
+        assert_eq!(queue.get_queue().len(), 3);
+        assert_eq!(queue.current_position, Some(0));
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("test2.mp3"));
+
+        queue.jump_to(2)?;
+        assert_eq!(queue.get_queue().len(), 2);
+        assert_eq!(queue.get_current_track().unwrap().path, PathBuf::from("test4.mp3"));
+        assert_eq!(queue.current_position, Some(1));
+
+        // Consumed tracks can be brought back with undo
+        queue.undo()?;
+        assert_eq!(queue.get_queue().len(), 3);
+        queue.redo()?;
+
+        // Finishing the last track empties the selection and stops
+        assert!(queue.advance_after_finish().is_err());
+        assert_eq!(queue.get_queue().len(), 1);
+        assert_eq!(queue.current_position, None);
+        assert_eq!(queue.playback_state, PlaybackState::Stopped);
+
+        let json = serde_json::to_string(&queue.to_state())?;
+        let restored = AudioQueue::from_state(serde_json::from_str(&json)?);
+        assert!(restored.is_consume_enabled());
+        assert!(queue.display_queue().contains("Consume: on"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_extended_m3u() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        write_wav(&dir.join("one.wav"), 2);
+        write_wav(&dir.join("two.wav"), 3);
+        std::fs::write(dir.join("corrupt.wav"), b"RIFF\x24\0\0\0WAVEfmt ")?;
+
+        let playlist = dir.join("show.m3u8");
+        std::fs::write(&playlist, "\u{feff}#EXTM3U\r\n\
+            #EXTINF:200,Some Artist - Described Title\r\n\
+            #EXTVLCOPT:stop-time=1.5\r\n\
+            one.wav\r\n\
+            # not a track\r\n\
+            corrupt.wav\r\n\
+            two.wav\r\n\
+            missing.wav\r\n")?;
+
+        let mut queue = AudioQueue::new();
+        queue.load_playlist(&playlist, &TagStore::new())?;
+        assert_eq!(queue.get_queue().len(), 2);
+
+        // #EXTINF is trusted over the file itself
+        let first = &queue.get_queue()[0];
+        assert_eq!(first.title.as_deref(), Some("Described Title"));
+        assert_eq!(first.artist.as_deref(), Some("Some Artist"));
+        assert_eq!(first.duration, Some(200.0));
+        assert_eq!(first.end, Some(1.5));
+
+        // Entries without #EXTINF are probed
+        let second = &queue.get_queue()[1];
+        assert_eq!(second.title.as_deref(), Some("two"));
+        assert_eq!(second.duration, Some(3.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pls_round_trip_matches_m3u() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let mut queue = AudioQueue::new();
+        for (name, seconds) in [("one", 2), ("two", 3), ("three", 1)] {
+            let file = dir.join(format!("{}.wav", name));
+            write_wav(&file, seconds);
+            let mut track = AudioQueue::extract_metadata(&file)?;
+            track.artist = Some("Station".to_string());
+            queue.add_track(track, None)?;
+        }
+
+        let m3u = dir.join("show.m3u");
+        let pls = dir.join("show.pls");
+        queue.save_playlist(&m3u)?;
+        queue.save_playlist(&pls)?;
+        let content = std::fs::read_to_string(&pls)?;
+        assert!(content.starts_with("[playlist]\n"));
+        assert!(content.contains("Title2=Station - two\nLength2=3\n"));
+        assert!(content.contains("NumberOfEntries=3\n"));
+
+        // Both formats load back to the same tracks
+        let summary = |queue: &AudioQueue| -> Vec<_> {
+            queue.get_queue().iter()
+                .map(|track| (track.path.clone(), track.title.clone(), track.artist.clone(), track.duration))
+                .collect()
+        };
+        let mut from_m3u = AudioQueue::new();
+        from_m3u.load_playlist(&m3u, &TagStore::new())?;
+        let mut from_pls = AudioQueue::new();
+        from_pls.load_playlist(&pls, &TagStore::new())?;
+        assert_eq!(summary(&from_pls), summary(&from_m3u));
+        assert_eq!(summary(&from_pls), summary(&queue));
+
+        // Ratings and labels of loaded tracks come from the tag store
+        let mut tags = TagStore::new();
+        tags.set_rating(dir.join("two.wav"), Some(4))?;
+        from_m3u.load_playlist(&m3u, &tags)?;
+        assert_eq!(from_m3u.filter_by_tags(&TagFilter { min_rating: Some(4), labels: Vec::new() }), vec![1]);
+
+        // Without a known extension the content decides
+        let unnamed = dir.join("show.playlist");
+        std::fs::copy(&pls, &unnamed)?;
+        assert_eq!(AudioQueue::read_playlist(&unnamed)?.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_xspf_round_trip() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("my music"))?;
+        let file = dir.join("my music").join("señal & ruido.wav");
+        write_wav(&file, 2);
+
+        let mut queue = AudioQueue::new();
+        let mut track = AudioQueue::extract_metadata(&file)?;
+        track.artist = Some("Los <Raros>".to_string());
+        track.album = Some("Live".to_string());
+        queue.add_track(track, None)?;
+        queue.set_trim(0, Some(0.5), None)?;
+
+        let playlist = dir.join("show.xspf");
+        queue.save_playlist(&playlist)?;
+        let content = std::fs::read_to_string(&playlist)?;
+        assert!(content.contains("<creator>Los &lt;Raros&gt;</creator>"));
+        assert!(content.contains("<duration>2000</duration>"));
+        assert!(content.contains("my%20music/se%C3%B1al%20&amp;%20ruido.wav</location>"));
+
+        let mut loaded = AudioQueue::new();
+        loaded.load_playlist(&playlist, &TagStore::new())?;
+        let track = &loaded.get_queue()[0];
+        assert_eq!(track.path, file.canonicalize()?);
+        assert_eq!(track.artist.as_deref(), Some("Los <Raros>"));
+        assert_eq!(track.album.as_deref(), Some("Live"));
+        assert_eq!(track.duration, Some(2.0));
+        assert_eq!(track.start, Some(0.5));
+
+        // Desktop players often write locations relative to the playlist
+        let relative = dir.join("relative.xspf");
+        std::fs::write(&relative, r#"<?xml version="1.0" encoding="UTF-8"?>
+            <playlist version="1" xmlns="http://xspf.org/ns/0/"><trackList>
+              <track><location>my%20music/se%C3%B1al%20%26%20ruido.wav</location></track>
+            </trackList></playlist>"#)?;
+        let tracks = AudioQueue::read_playlist(&relative)?;
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].duration, Some(2.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cue_sheet_tracks() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let file = dir.join("album.wav");
+        write_wav(&file, 10);
+        let cue = dir.join("album.cue");
+        std::fs::write(&cue, "PERFORMER \"The Band\"\nTITLE \"One File Album\"\n\
+            FILE \"album.wav\" WAVE\n\
+            \x20 TRACK 01 AUDIO\n    TITLE \"Intro\"\n    INDEX 01 00:00:00\n\
+            \x20 TRACK 02 AUDIO\n    TITLE \"Middle\"\n    INDEX 00 00:02:00\n    INDEX 01 00:03:00\n\
+            \x20 TRACK 03 AUDIO\n    TITLE \"Outro\"\n    PERFORMER \"Guest\"\n    INDEX 01 00:07:37\n")?;
+
+        let tracks = AudioQueue::read_playlist(&cue)?;
+        assert_eq!(tracks.len(), 3);
+        assert!(tracks.iter().all(|track| track.path == file.canonicalize().unwrap()));
+        assert_eq!(tracks[0].title.as_deref(), Some("Intro"));
+        assert_eq!(tracks[0].artist.as_deref(), Some("The Band"));
+        assert_eq!(tracks[0].album.as_deref(), Some("One File Album"));
+        assert_eq!(tracks[0].playable_duration(), Some(3.0));
+        assert_eq!((tracks[1].start, tracks[1].end), (Some(3.0), Some(7.0 + 37.0 / 75.0)));
+        assert_eq!(tracks[2].artist.as_deref(), Some("Guest"));
+        // The last track plays to the end of the file
+        assert_eq!(tracks[2].end, None);
+        assert!((tracks[2].playable_duration().unwrap() - (3.0 - 37.0 / 75.0)).abs() < 1e-9);
+
+        // Regions of one file are distinct tracks, not duplicates
+        let mut queue = AudioQueue::new();
+        queue.set_duplicate_policy(DuplicatePolicy::Skip, false);
+        for track in tracks {
+            assert!(matches!(queue.add_track(track, None)?, AddOutcome::Added(_)));
+        }
+        let again = AudioQueue::read_playlist(&cue)?.remove(1);
+        assert_eq!(queue.add_track(again, None)?, AddOutcome::Skipped(1));
+
+        queue.jump_to(0)?;
+        queue.next_track()?;
+        assert_eq!(queue.get_current_track().unwrap().title.as_deref(), Some("Middle"));
+        queue.previous()?;
+        assert_eq!(queue.get_current_track().unwrap().title.as_deref(), Some("Intro"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_playlist_survives_copy() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        let library = dir.join("library");
+        std::fs::create_dir_all(library.join("music").join("café"))?;
+        std::fs::create_dir_all(library.join("lists"))?;
+        let file = library.join("music").join("café").join("song #1.wav");
+        write_wav(&file, 1);
+
+        let mut queue = AudioQueue::new();
+        queue.add_track(AudioQueue::extract_metadata(&file)?, None)?;
+        for name in ["show.m3u", "show.pls", "show.xspf"] {
+            let playlist = library.join("lists").join(name);
+            let format = PlaylistFormat::from_extension(&playlist).unwrap();
+            queue.export_playlist(&playlist, format, &TagFilter::default(), true)?;
+        }
+        let m3u = std::fs::read_to_string(library.join("lists").join("show.m3u"))?;
+        assert!(m3u.ends_with("\n../music/café/song #1.wav\n"));
+        let xspf = std::fs::read_to_string(library.join("lists").join("show.xspf"))?;
+        assert!(xspf.contains("<location>../music/caf%C3%A9/song%20%231.wav</location>"));
+
+        // The playlists keep working when the whole folder moves
+        let moved = dir.join("moved");
+        std::fs::rename(&library, &moved)?;
+        let moved_file = moved.join("music").join("café").join("song #1.wav").canonicalize()?;
+        for name in ["show.m3u", "show.pls", "show.xspf"] {
+            let tracks = AudioQueue::read_playlist(moved.join("lists").join(name))?;
+            assert_eq!(tracks.len(), 1, "{}", name);
+            assert_eq!(tracks[0].path, moved_file);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_tracks_is_one_undo_step() -> Result<()> {
+        let mut queue = AudioQueue::new();
+        queue.add_track(create_test_track("mine.mp3", None, None), None)?;
+        queue.set_duplicate_policy(DuplicatePolicy::Skip, false);
+
+        let playlist: Vec<AudioTrack> = (0..MAX_JOURNAL_DEPTH + 10)
+            .map(|i| create_test_track(&format!("t{}.mp3", i % (MAX_JOURNAL_DEPTH + 5)), None, None))
+            .collect();
+        assert_eq!(queue.replace_tracks(playlist.clone()), MAX_JOURNAL_DEPTH + 5);
+        assert_eq!(queue.get_queue().len(), MAX_JOURNAL_DEPTH + 5);
+
+        queue.undo()?;
+        assert_eq!(queue.get_queue().len(), 1);
+        assert_eq!(queue.get_queue()[0].path, PathBuf::from("mine.mp3"));
+
+        // Appending is a single step too, and skips what is already queued
+        assert_eq!(queue.add_tracks(playlist), MAX_JOURNAL_DEPTH + 5);
+        assert_eq!(queue.add_tracks(vec![create_test_track("mine.mp3", None, None)]), 0);
+        queue.undo()?;
+        assert_eq!(queue.get_queue().len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_restore_can_be_undone() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let library = SnapshotLibrary::new(dir.path());
+        let paths = |queue: &AudioQueue| -> Vec<PathBuf> {
+            queue.get_queue().iter().map(|track| track.path.clone()).collect()
+        };
+
+        let mut queue = AudioQueue::new();
+        for path in ["a.mp3", "b.mp3"] {
+            queue.add_track(create_test_track(path, None, None), None)?;
+        }
+        queue.jump_to(1)?;
+        queue.play()?;
+        library.save("before-show", queue.to_state())?;
+
+        queue.clear()?;
+        queue.add_track(create_test_track("c.mp3", None, None), None)?;
+        let history = queue.get_history().len();
+
+        queue.restore(library.load("before-show")?.state);
+        assert_eq!(paths(&queue), ["a.mp3", "b.mp3"].map(PathBuf::from));
+        assert_eq!(queue.current_position, Some(1));
+        assert_eq!(queue.get_history().len(), history);
+
+        // Restoring is one step, and the edits before it can still be undone
+        queue.undo()?;
+        assert_eq!(paths(&queue), ["c.mp3"].map(PathBuf::from));
+        queue.undo()?;
+        queue.undo()?;
+        assert_eq!(paths(&queue), ["a.mp3", "b.mp3"].map(PathBuf::from));
+        queue.redo()?;
+        queue.redo()?;
+        queue.redo()?;
+        assert_eq!(paths(&queue), ["a.mp3", "b.mp3"].map(PathBuf::from));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_smart_playlist_evaluate_and_library() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("sub"))?;
+        write_wav(&dir.join("short.wav"), 1);
+        write_wav(&dir.join("long.wav"), 3);
+        write_wav(&dir.join("sub").join("medium.wav"), 2);
+        std::fs::write(dir.join("notes.txt"), "not audio")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir, dir.join("sub").join("loop"))?;
+
+        let mut playlist = SmartPlaylist::new(vec![dir.to_path_buf()]);
+        playlist.rules = vec![Rule::Extension(vec!["wav".to_string()])];
+        playlist.sort = Some(SortKey::Duration);
+        playlist.order = SortOrder::Descending;
+        playlist.limit = Some(2);
+
+        let names = |tracks: &[AudioTrack]| -> Vec<String> {
+            tracks.iter().map(|track| track.path.file_name().unwrap().to_string_lossy().to_string()).collect()
+        };
+        let tracks = playlist.evaluate(&StatsStore::new())?;
+        assert_eq!(names(&tracks), ["long.wav", "medium.wav"]);
+        assert!(tracks.iter().all(|track| track.id == 0));
+
+        playlist.recursive = false;
+        playlist.rules.push(Rule::Duration { min: None, max: Some(2.0) });
+        let tracks = playlist.evaluate(&StatsStore::new())?;
+        assert_eq!(names(&tracks), ["short.wav"]);
+
+        let library = SmartPlaylistLibrary::new(dir.join("definitions"));
+        library.save("short", &playlist)?;
+        assert!(library.save("../escape", &playlist).is_err());
+        assert_eq!(library.names()?, ["short"]);
+        assert_eq!(library.load("short")?, playlist);
+        library.delete("short")?;
+        assert!(library.load("short").is_err());
+
+        Ok(())
+    }
 }
 
 // Integration tests with real files
@@ -463,7 +1684,7 @@ mod integration_tests {
 
         // Test loading playlist
         let mut queue = AudioQueue::new();
-        match queue.load_playlist(&playlist_path) {
+        match queue.load_playlist(&playlist_path, &TagStore::new()) {
             Ok(_) => {
                 println!("  ✓ Loaded playlist: {}", playlist_path.display());
                 println!("    Tracks loaded: {}", queue.get_queue().len());
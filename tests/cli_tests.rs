@@ -0,0 +1,138 @@
+use std::path::Path;
+use std::process::{Command, Output};
+use anyhow::Result;
+
+/// Run the audioqueue binary with its state files kept in `dir`
+fn audioqueue(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_audioqueue"))
+        .args(args)
+        .env("TMPDIR", dir)
+        .output()
+        .expect("failed to run audioqueue")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn no_audio_device(output: &Output) -> bool {
+    stderr(output).contains("Failed to create audio output stream")
+}
+
+fn state_file(dir: &Path) -> std::path::PathBuf {
+    dir.join("audioqueue_state.json")
+}
+
+/// Put tracks titled `titles` in the default queue. Tracks need real files to be
+/// added through the CLI, so they are written to the state file instead.
+fn seed_tracks(dir: &Path, titles: &[&str]) -> Result<()> {
+    let state = state_file(dir);
+    let mut collection: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&state)?)?;
+    let tracks: Vec<serde_json::Value> = titles.iter()
+        .enumerate()
+        .map(|(position, title)| serde_json::json!({
+            "id": position + 1,
+            "path": format!("{}.mp3", title.to_lowercase()),
+            "title": title,
+            "artist": null,
+            "duration": 60.0,
+            "position": position,
+        }))
+        .collect();
+    collection["queues"]["default"]["tracks"] = serde_json::json!(tracks);
+    collection["queues"]["default"]["next_id"] = serde_json::json!(titles.len() + 1);
+    std::fs::write(&state, collection.to_string())?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unknown_queue_suggests_creating_it() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let output = audioqueue(dir.path(), &["--queue", "party", "list"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("create it with `audioqueue queue create party`"));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_invalid_state_file_is_backed_up() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(state_file(dir.path()), "{ not json")?;
+
+    let output = audioqueue(dir.path(), &["queue", "list"]);
+    assert!(stderr(&output).contains("moved it to"));
+    assert_eq!(std::fs::read_to_string(dir.path().join("audioqueue_state.json.bak"))?, "{ not json");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_queue_create_switch_copy_move() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let output = audioqueue(dir.path(), &["queue", "create", "party"]);
+    if no_audio_device(&output) {
+        println!("Skipping: no audio device available");
+        return Ok(());
+    }
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    seed_tracks(dir.path(), &["A", "B", "C"])?;
+
+    assert!(audioqueue(dir.path(), &["queue", "copy", "0", "--to", "party"]).status.success());
+    assert!(audioqueue(dir.path(), &["queue", "move", "1", "2", "--to", "party"]).status.success());
+    assert!(!audioqueue(dir.path(), &["queue", "copy", "0", "--to", "missing"]).status.success());
+
+    let listed = stdout(&audioqueue(dir.path(), &["queue", "list"]));
+    assert!(listed.contains("* default (1 tracks)"), "{}", listed);
+    assert!(listed.contains("  party (3 tracks)"), "{}", listed);
+
+    assert!(audioqueue(dir.path(), &["queue", "switch", "party"]).status.success());
+    let listed = stdout(&audioqueue(dir.path(), &["queue", "list"]));
+    assert!(listed.contains("* party (3 tracks)"), "{}", listed);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_position_overflow_is_a_parse_error() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    for positions in ["18446744073709551615", "0..=18446744073709551615"] {
+        let output = audioqueue(dir.path(), &["remove", positions]);
+        assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+        assert!(stderr(&output).contains("is too large"), "{}", stderr(&output));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_find_position_feeds_remove() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let output = audioqueue(dir.path(), &["clear"]);
+    if no_audio_device(&output) {
+        println!("Skipping: no audio device available");
+        return Ok(());
+    }
+    seed_tracks(dir.path(), &["Alpha", "Bravo", "Charlie"])?;
+
+    let found = stdout(&audioqueue(dir.path(), &["find", "bravo"]));
+    let position = found.lines()
+        .find_map(|line| line.trim().strip_prefix("pos "))
+        .and_then(|rest| rest.split(':').next())
+        .expect("find lists the match");
+    assert!(audioqueue(dir.path(), &["remove", position]).status.success());
+
+    let collection: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(state_file(dir.path()))?)?;
+    let titles: Vec<&str> = collection["queues"]["default"]["tracks"].as_array().unwrap()
+        .iter()
+        .filter_map(|track| track["title"].as_str())
+        .collect();
+    assert_eq!(titles, vec!["Alpha", "Charlie"]);
+    Ok(())
+}